@@ -0,0 +1,5 @@
+ALTER TABLE
+  captions DROP COLUMN kind;
+
+ALTER TABLE
+  captions DROP COLUMN language;
@@ -0,0 +1,14 @@
+ALTER TABLE
+  captions
+ADD
+  COLUMN language text not null default 'en';
+
+ALTER TABLE
+  captions
+ALTER COLUMN
+  language drop default;
+
+ALTER TABLE
+  captions
+ADD
+  COLUMN kind text;
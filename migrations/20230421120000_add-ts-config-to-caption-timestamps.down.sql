@@ -0,0 +1,6 @@
+drop index caption_text_index;
+
+ALTER TABLE
+  caption_timestamps DROP COLUMN ts_config;
+
+create index caption_text_index on caption_timestamps using gin(to_tsvector('english', caption_text));
@@ -0,0 +1,13 @@
+ALTER TABLE
+  caption_timestamps
+ADD
+  COLUMN ts_config regconfig not null default 'english';
+
+ALTER TABLE
+  caption_timestamps
+ALTER COLUMN
+  ts_config drop default;
+
+drop index caption_text_index;
+
+create index caption_text_index on caption_timestamps using gin(to_tsvector(ts_config, caption_text));
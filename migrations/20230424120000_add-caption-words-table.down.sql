@@ -0,0 +1 @@
+drop table caption_words;
@@ -0,0 +1,13 @@
+create table caption_words (
+  id serial primary key,
+  video_id int not null,
+  caption_id int not null,
+  caption_timestamp_id int not null,
+  word text not null,
+  start float not null,
+  foreign key (video_id) references videos(id),
+  foreign key (caption_id) references captions(id),
+  foreign key (caption_timestamp_id) references caption_timestamps(id)
+);
+
+create index caption_words_caption_timestamp_id_index on caption_words(caption_timestamp_id);
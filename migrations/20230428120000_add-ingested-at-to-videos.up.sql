@@ -0,0 +1,9 @@
+ALTER TABLE
+  videos
+ADD
+  COLUMN ingested_at timestamp with time zone not null default now();
+
+update
+  videos
+set
+  ingested_at = upload_datetime;
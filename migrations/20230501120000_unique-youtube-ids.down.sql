@@ -0,0 +1,3 @@
+drop index channels_youtube_id_unique;
+
+drop index videos_youtube_id_unique;
@@ -0,0 +1,101 @@
+create temporary table duplicate_videos as
+select
+  v.id,
+  (
+    select
+      min(v2.id)
+    from
+      videos v2
+    where
+      v2.youtube_id = v.youtube_id
+  ) as kept_id
+from
+  videos v
+where
+  v.youtube_id != ''
+  and exists (
+    select
+      1
+    from
+      videos v2
+    where
+      v2.youtube_id = v.youtube_id
+      and v2.id < v.id
+  );
+
+update
+  submissions s
+set
+  video_id = d.kept_id
+from
+  duplicate_videos d
+where
+  s.video_id = d.id;
+
+delete from
+  caption_words
+where
+  video_id in (select id from duplicate_videos);
+
+delete from
+  caption_timestamps
+where
+  video_id in (select id from duplicate_videos);
+
+delete from
+  captions
+where
+  video_id in (select id from duplicate_videos);
+
+delete from
+  videos
+where
+  id in (select id from duplicate_videos);
+
+create temporary table duplicate_channels as
+select
+  c.id,
+  (
+    select
+      min(c2.id)
+    from
+      channels c2
+    where
+      c2.youtube_id = c.youtube_id
+  ) as kept_id
+from
+  channels c
+where
+  c.youtube_id != ''
+  and exists (
+    select
+      1
+    from
+      channels c2
+    where
+      c2.youtube_id = c.youtube_id
+      and c2.id < c.id
+  );
+
+update
+  videos v
+set
+  channel_id = d.kept_id
+from
+  duplicate_channels d
+where
+  v.channel_id = d.id;
+
+delete from
+  channels
+where
+  id in (select id from duplicate_channels);
+
+-- Rows from before the youtube_id columns were added all have ''
+create unique index videos_youtube_id_unique on videos(youtube_id)
+where
+  youtube_id != '';
+
+create unique index channels_youtube_id_unique on channels(youtube_id)
+where
+  youtube_id != '';
@@ -0,0 +1 @@
+drop table ingestion_jobs;
@@ -0,0 +1,18 @@
+create table ingestion_jobs (
+  id serial primary key,
+  youtube_id text not null,
+  caption_preferences jsonb not null,
+  refresh boolean not null default false,
+  status text not null default 'queued',
+  error text,
+  error_message text,
+  video_id int,
+  already_existed boolean,
+  created_at timestamptz not null default now(),
+  started_at timestamptz,
+  finished_at timestamptz,
+  foreign key (video_id) references videos(id),
+  check (status in ('queued', 'running', 'succeeded', 'failed'))
+);
+
+create index ingestion_jobs_queued_index on ingestion_jobs(id) where status = 'queued';
@@ -0,0 +1,4 @@
+ALTER TABLE
+  channels DROP COLUMN tracked,
+  DROP COLUMN uploads_playlist_id,
+  DROP COLUMN last_synced_at;
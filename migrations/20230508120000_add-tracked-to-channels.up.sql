@@ -0,0 +1,8 @@
+ALTER TABLE
+  channels
+ADD
+  COLUMN tracked boolean not null default false,
+ADD
+  COLUMN uploads_playlist_id text,
+ADD
+  COLUMN last_synced_at timestamp with time zone;
@@ -0,0 +1,7 @@
+create table api_quota_usage (
+  day date not null,
+  call_type text not null,
+  calls bigint not null default 0,
+  units bigint not null default 0,
+  primary key (day, call_type)
+);
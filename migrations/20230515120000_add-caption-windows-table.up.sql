@@ -0,0 +1,50 @@
+-- Each caption line joined with the line after it, so phrases split across
+-- two lines can still be found
+create table caption_windows (
+  id serial primary key,
+  video_id int not null,
+  caption_id int not null,
+  caption_timestamp_id int not null,
+  next_caption_timestamp_id int not null,
+  window_text text not null,
+  ts_config regconfig not null,
+  foreign key (video_id) references videos(id),
+  foreign key (caption_id) references captions(id),
+  foreign key (caption_timestamp_id) references caption_timestamps(id),
+  foreign key (next_caption_timestamp_id) references caption_timestamps(id)
+);
+
+create index caption_window_text_index on caption_windows using gin(to_tsvector(ts_config, window_text));
+
+insert into
+  caption_windows (
+    video_id,
+    caption_id,
+    caption_timestamp_id,
+    next_caption_timestamp_id,
+    window_text,
+    ts_config
+  )
+select
+  video_id,
+  caption_id,
+  id,
+  next_id,
+  caption_text || ' ' || next_text,
+  ts_config
+from
+  (
+    select
+      *,
+      lead(id) over line as next_id,
+      lead(caption_text) over line as next_text
+    from
+      caption_timestamps window line as (
+        partition by caption_id
+        order by
+          start,
+          id
+      )
+  ) lines
+where
+  next_id is not null;
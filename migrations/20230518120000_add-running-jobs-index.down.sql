@@ -0,0 +1 @@
+drop index ingestion_jobs_running_index;
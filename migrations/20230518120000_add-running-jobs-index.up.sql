@@ -0,0 +1 @@
+create index ingestion_jobs_running_index on ingestion_jobs(started_at) where status = 'running';
@@ -0,0 +1 @@
+drop index caption_windows_caption_id_index;
@@ -0,0 +1 @@
+create index caption_windows_caption_id_index on caption_windows(caption_id);
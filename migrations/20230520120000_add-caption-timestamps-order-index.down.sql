@@ -0,0 +1 @@
+drop index caption_timestamps_caption_id_start_index;
@@ -0,0 +1,3 @@
+-- Lines of a track in the order they're said, for stepping out from a search
+-- hit to the lines around it
+create index caption_timestamps_caption_id_start_index on caption_timestamps(caption_id, start, id);
@@ -0,0 +1,4 @@
+ALTER TABLE
+  captions
+ALTER COLUMN
+  kind DROP not null;
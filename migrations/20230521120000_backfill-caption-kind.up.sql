@@ -0,0 +1,15 @@
+-- Captions ingested before we stored the kind came from the last track on
+-- the watch page, which is the auto-generated one whenever a video has one
+update
+  captions
+set
+  kind = 'asr'
+where
+  kind is null;
+
+ALTER TABLE
+  captions
+ALTER COLUMN
+  kind
+SET
+  not null;
@@ -0,0 +1,328 @@
+use crate::ingest::{IngestError, IngestOptions};
+use crate::jobs::{JobQueue, QueuedVideo};
+use crate::utils::captions::default_caption_preferences;
+use crate::utils::feed::{feed_url, parse_channel_feed, FeedError};
+use crate::utils::http::HttpClient;
+use crate::youtube::{ChannelDetails, YouTubeApi};
+use sqlx::{Error, FromRow, PgPool};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct TrackedChannel {
+    pub id: i32,
+    pub youtube_id: String,
+}
+
+// Adds or updates a channel we looked up through the Data API, returning its id
+pub async fn save_channel(
+    pool: &PgPool,
+    channel: &ChannelDetails,
+    tracked: bool,
+) -> Result<i32, Error> {
+    sqlx::query_scalar(
+        "insert into channels (title, url, thumbnail, youtube_id, tracked, uploads_playlist_id) values ($1, $2, $3, $4, $5, $6)
+        on conflict (youtube_id) where youtube_id != '' do update set title=excluded.title, thumbnail=excluded.thumbnail, tracked=excluded.tracked, uploads_playlist_id=excluded.uploads_playlist_id
+        returning id",
+    )
+    .bind(&channel.title)
+    .bind(format!("https://youtube.com/channel/{}", channel.youtube_id))
+    .bind(&channel.thumbnail)
+    .bind(&channel.youtube_id)
+    .bind(tracked)
+    .bind(&channel.uploads_playlist_id)
+    .fetch_one(pool)
+    .await
+}
+
+// Queues the videos in a channel's uploads playlist that we haven't indexed yet
+pub async fn sync_channel(
+    pool: &PgPool,
+    youtube: &dyn YouTubeApi,
+    jobs: &JobQueue,
+    channel_id: i32,
+    uploads_playlist_id: &str,
+    options: &IngestOptions,
+) -> Result<Vec<QueuedVideo>, IngestError> {
+    let youtube_ids = youtube.playlist_video_ids(uploads_playlist_id).await?;
+    let videos = jobs.enqueue_videos(youtube_ids, options).await?;
+
+    sqlx::query("update channels set last_synced_at=now() where id=$1")
+        .bind(channel_id)
+        .execute(pool)
+        .await?;
+
+    Ok(videos)
+}
+
+#[derive(Debug)]
+pub enum PollError {
+    Request(reqwest::Error),
+    Feed(FeedError),
+    Database(Error),
+}
+
+impl fmt::Display for PollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PollError::Request(e) => write!(f, "Request for channel feed failed: {e}"),
+            PollError::Feed(e) => write!(f, "{e}"),
+            PollError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PollError {}
+
+impl From<reqwest::Error> for PollError {
+    fn from(e: reqwest::Error) -> Self {
+        PollError::Request(e)
+    }
+}
+
+impl From<FeedError> for PollError {
+    fn from(e: FeedError) -> Self {
+        PollError::Feed(e)
+    }
+}
+
+impl From<Error> for PollError {
+    fn from(e: Error) -> Self {
+        PollError::Database(e)
+    }
+}
+
+// Queues a tracked channel's new uploads, going by its Atom feed rather than
+// the Data API so polling doesn't cost any quota. Videos we've indexed or
+// already have a job for (even a failed one) are left alone.
+pub async fn poll_channel_feed(
+    pool: &PgPool,
+    http: &HttpClient,
+    jobs: &JobQueue,
+    feed_base_url: &str,
+    channel: &TrackedChannel,
+) -> Result<Vec<QueuedVideo>, PollError> {
+    let feed = http
+        .get(feed_url(feed_base_url, &channel.youtube_id))
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let youtube_ids: Vec<String> = parse_channel_feed(&feed)?
+        .into_iter()
+        .map(|entry| entry.video_id)
+        .collect();
+
+    let known: Vec<String> = sqlx::query_scalar(
+        "select youtube_id from videos where youtube_id = any($1)
+        union
+        select youtube_id from ingestion_jobs where youtube_id = any($1)",
+    )
+    .bind(&youtube_ids)
+    .fetch_all(pool)
+    .await?;
+    let new_youtube_ids: Vec<String> = youtube_ids
+        .into_iter()
+        .filter(|youtube_id| !known.contains(youtube_id))
+        .collect();
+
+    let options = IngestOptions {
+        caption_preferences: default_caption_preferences(),
+        refresh: false,
+    };
+    let videos = jobs.enqueue_videos(new_youtube_ids, &options).await?;
+
+    sqlx::query("update channels set last_synced_at=now() where id=$1")
+        .bind(channel.id)
+        .execute(pool)
+        .await?;
+
+    Ok(videos)
+}
+
+async fn poll_tracked_channels(
+    pool: &PgPool,
+    http: &HttpClient,
+    jobs: &JobQueue,
+    feed_base_url: &str,
+) {
+    let channels = sqlx::query_as::<_, TrackedChannel>(
+        "select id, youtube_id from channels where tracked and youtube_id != '' order by id",
+    )
+    .fetch_all(pool)
+    .await;
+
+    let channels = match channels {
+        Ok(channels) => channels,
+        Err(e) => {
+            eprintln!("Could not load tracked channels: {e}");
+            return;
+        }
+    };
+
+    for channel in channels {
+        if let Err(e) = poll_channel_feed(pool, http, jobs, feed_base_url, &channel).await {
+            eprintln!("Could not poll channel {}: {e}", channel.youtube_id);
+        }
+    }
+}
+
+// Checks every tracked channel's feed for new uploads once per `interval`
+pub fn start_channel_sync(
+    pool: PgPool,
+    http: HttpClient,
+    jobs: JobQueue,
+    feed_base_url: String,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            poll_tracked_channels(&pool, &http, &jobs, &feed_base_url).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server;
+
+    fn channel(title: &str) -> ChannelDetails {
+        ChannelDetails {
+            youtube_id: "UCuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+            title: title.to_string(),
+            thumbnail: "https://yt3.ggpht.com/rick".to_string(),
+            uploads_playlist_id: "UUuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+        }
+    }
+
+    #[sqlx::test]
+    async fn save_channel_updates_existing_channels(pool: PgPool) {
+        let id = save_channel(&pool, &channel("Rick Astley"), true)
+            .await
+            .unwrap();
+        let again = save_channel(&pool, &channel("Rick Astley (official)"), false)
+            .await
+            .unwrap();
+
+        assert_eq!(id, again);
+        let (title, tracked): (String, bool) =
+            sqlx::query_as("select title, tracked from channels where id=$1")
+                .bind(id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(title, "Rick Astley (official)");
+        assert!(!tracked);
+    }
+
+    async fn serve_feeds() -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        let feed =
+            std::fs::read_to_string("tests/fixtures/feeds/UCuAXFkgsw1L7xaCfnd5JJOw.xml").unwrap();
+        let (base_url, requests) = test_server::serve(move |target| {
+            if target.ends_with("channel_id=UCuAXFkgsw1L7xaCfnd5JJOw") {
+                test_server::response("200 OK", &[("Content-Type", "text/xml")], &feed)
+            } else {
+                test_server::response("404 Not Found", &[], "")
+            }
+        })
+        .await;
+
+        (format!("{base_url}/feeds/videos.xml"), requests)
+    }
+
+    async fn tracked_channel(pool: &PgPool, youtube_id: &str, tracked: bool) -> TrackedChannel {
+        let id = save_channel(
+            pool,
+            &ChannelDetails {
+                youtube_id: youtube_id.to_string(),
+                ..channel("Rick Astley")
+            },
+            tracked,
+        )
+        .await
+        .unwrap();
+
+        TrackedChannel {
+            id,
+            youtube_id: youtube_id.to_string(),
+        }
+    }
+
+    fn queued_ids(videos: &[QueuedVideo]) -> Vec<&str> {
+        videos
+            .iter()
+            .filter(|v| v.job_id.is_some())
+            .map(|v| v.youtube_id.as_str())
+            .collect()
+    }
+
+    #[sqlx::test]
+    async fn polling_queues_only_new_uploads(pool: PgPool) {
+        let (feed_base_url, _) = serve_feeds().await;
+        let http = HttpClient::new().unwrap();
+        let jobs = JobQueue::new(pool.clone());
+        let channel = tracked_channel(&pool, "UCuAXFkgsw1L7xaCfnd5JJOw", true).await;
+
+        // dQw4w9WgXcQ is in the feed but we've already indexed it
+        sqlx::query("insert into videos (channel_id, title, url, upload_datetime, views, length, thumbnail, youtube_id) values ($1, 'Never Gonna Give You Up', 'https://www.youtube.com/watch?v=dQw4w9WgXcQ', now(), 0, 213, '', 'dQw4w9WgXcQ')")
+            .bind(channel.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let videos = poll_channel_feed(&pool, &http, &jobs, &feed_base_url, &channel)
+            .await
+            .unwrap();
+        assert_eq!(queued_ids(&videos), vec!["jNQXAC9IVRw"]);
+
+        // The new upload has a job now, so it isn't queued twice
+        let videos = poll_channel_feed(&pool, &http, &jobs, &feed_base_url, &channel)
+            .await
+            .unwrap();
+        assert!(videos.is_empty());
+
+        let last_synced_at: Option<chrono::DateTime<chrono::Utc>> =
+            sqlx::query_scalar("select last_synced_at from channels where id=$1")
+                .bind(channel.id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(last_synced_at.is_some());
+    }
+
+    #[sqlx::test]
+    async fn polling_reports_missing_feeds(pool: PgPool) {
+        let (feed_base_url, _) = serve_feeds().await;
+        let http = HttpClient::new().unwrap();
+        let jobs = JobQueue::new(pool.clone());
+        let channel = tracked_channel(&pool, "UCxxxxxxxxxxxxxxxxxxxxxx", true).await;
+
+        let result = poll_channel_feed(&pool, &http, &jobs, &feed_base_url, &channel).await;
+
+        assert!(matches!(result, Err(PollError::Request(_))));
+    }
+
+    #[sqlx::test]
+    async fn only_tracked_channels_are_polled(pool: PgPool) {
+        let (feed_base_url, requests) = serve_feeds().await;
+        let http = HttpClient::new().unwrap();
+        let jobs = JobQueue::new(pool.clone());
+        tracked_channel(&pool, "UCuAXFkgsw1L7xaCfnd5JJOw", true).await;
+        tracked_channel(&pool, "UCxxxxxxxxxxxxxxxxxxxxxx", false).await;
+
+        poll_tracked_channels(&pool, &http, &jobs, &feed_base_url).await;
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["/feeds/videos.xml?channel_id=UCuAXFkgsw1L7xaCfnd5JJOw"]
+        );
+        let queued: i64 = sqlx::query_scalar("select count(*) from ingestion_jobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(queued, 2);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::ingest::fetch_published_dates;
+use crate::youtube::YouTubeApi;
+use sqlx::{PgConnection, PgPool};
+
+// Maintenance commands, run with `cargo run -- <command>` instead of starting
+// the server
+pub async fn run(command: &str, pool: &PgPool, youtube: &dyn YouTubeApi) -> Result<(), String> {
+    match command {
+        "backfill-published-at" => backfill_published_at(pool, youtube).await,
+        _ => Err(format!(
+            "Unknown command \"{command}\". Available commands: backfill-published-at"
+        )),
+    }
+}
+
+// Videos ingested before we stored publish dates have their ingestion time in
+// `upload_datetime` (which the migration also copied into `ingested_at`). This
+// re-queries the Data API for those and stores the real publish date.
+async fn backfill_published_at(pool: &PgPool, youtube: &dyn YouTubeApi) -> Result<(), String> {
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    let youtube_ids = videos_missing_published_at(&mut conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    println!("Backfilling publish dates for {} videos", youtube_ids.len());
+
+    let published_dates = fetch_published_dates(youtube, &youtube_ids)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for (youtube_id, published_at) in &published_dates {
+        sqlx::query("update videos set upload_datetime=$1 where youtube_id=$2")
+            .bind(published_at)
+            .bind(youtube_id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    println!(
+        "Updated {} videos, {} could not be found on YouTube",
+        published_dates.len(),
+        youtube_ids.len() - published_dates.len()
+    );
+
+    Ok(())
+}
+
+// Both columns are `timestamptz`, so they compare as instants whatever the
+// session's TimeZone is. Converting either one `at time zone` would make it a
+// plain `timestamp`, and comparing that is what depends on the TimeZone.
+async fn videos_missing_published_at(conn: &mut PgConnection) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "select youtube_id from videos where upload_datetime = ingested_at and youtube_id != ''",
+    )
+    .fetch_all(conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::{parse_published_at, save_video, NewChannel, VideoToIngest};
+
+    fn video_to_ingest(youtube_id: &str) -> VideoToIngest {
+        VideoToIngest {
+            youtube_id: youtube_id.to_string(),
+            channel_youtube_id: "UCuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+            new_channel: Some(NewChannel {
+                title: "Rick Astley".to_string(),
+                thumbnail: String::new(),
+            }),
+            title: youtube_id.to_string(),
+            published_at: parse_published_at("2009-10-25T06:57:33Z").unwrap(),
+            views: 0,
+            length: 0,
+            thumbnail: String::new(),
+            caption_sets: vec![],
+        }
+    }
+
+    #[sqlx::test]
+    async fn finds_videos_missing_published_at_in_any_time_zone(pool: PgPool) {
+        save_video(&pool, &video_to_ingest("dQw4w9WgXcQ"), None)
+            .await
+            .unwrap();
+        // Ingested before we stored publish dates
+        sqlx::query("update videos set ingested_at = '2023-04-01 12:00:00+00', upload_datetime = '2023-04-01 12:00:00+00' where youtube_id = 'dQw4w9WgXcQ'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        for time_zone in ["UTC", "America/New_York", "Asia/Kolkata"] {
+            sqlx::query(&format!("set time zone '{time_zone}'"))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+
+            assert_eq!(
+                videos_missing_published_at(&mut conn).await.unwrap(),
+                vec!["dQw4w9WgXcQ"],
+                "{time_zone}"
+            );
+        }
+    }
+}
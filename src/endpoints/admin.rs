@@ -0,0 +1,16 @@
+use super::general::{database_error_response, ApiState, ErrorResponse};
+use crate::quota::QuotaUsage;
+use rocket::get;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+// How many Data API units we've spent today (Pacific time), by call type
+#[get("/admin/quota")]
+pub async fn get_quota(
+    state: &State<ApiState>,
+) -> Result<Json<QuotaUsage>, status::Custom<Json<ErrorResponse>>> {
+    let usage = state.quota.usage().await.map_err(database_error_response)?;
+
+    Ok(Json(usage))
+}
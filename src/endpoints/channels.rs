@@ -0,0 +1,107 @@
+use super::general::{
+    database_error_response, ingest_error_response, youtube_error_response, ApiState, ErrorResponse,
+};
+use crate::channels::{save_channel, sync_channel};
+use crate::ingest::IngestOptions;
+use crate::jobs::QueuedVideo;
+use crate::utils::captions::{default_caption_preferences, CaptionPreference};
+use crate::utils::youtube_url::parse_channel;
+use rocket::http::Status;
+use rocket::post;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+#[derive(Debug, Deserialize)]
+pub struct NewChannelUrl {
+    // A channel url, an @handle, or the channel id
+    pub url: String,
+    pub caption_preferences: Option<Vec<CaptionPreference>>,
+    // Keep checking the channel for new uploads. Defaults to true; send false
+    // to stop tracking a channel.
+    pub track: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreateChannelResponse {
+    pub success: bool,
+    pub id: i32,
+    pub youtube_id: String,
+    pub title: String,
+    pub tracked: bool,
+    pub queued: usize,
+    pub skipped: usize,
+    pub videos: Vec<QueuedVideo>,
+}
+
+// Queues every upload of a channel for ingestion, or with `track: false`
+// just stops tracking it
+#[post("/channel", data = "<channel_url>")]
+pub async fn create_channel(
+    channel_url: Json<NewChannelUrl>,
+    state: &State<ApiState>,
+) -> Result<Json<CreateChannelResponse>, status::Custom<Json<ErrorResponse>>> {
+    let channel_ref = parse_channel(&channel_url.url).map_err(|e| {
+        status::Custom(
+            Status::BadRequest,
+            Json(ErrorResponse::new("invalid_url", e.to_string())),
+        )
+    })?;
+
+    let options = IngestOptions {
+        caption_preferences: channel_url
+            .caption_preferences
+            .clone()
+            .unwrap_or_else(default_caption_preferences),
+        refresh: false,
+    };
+    let tracked = channel_url.track.unwrap_or(true);
+
+    let channel = state
+        .youtube
+        .channel(&channel_ref)
+        .await
+        .map_err(youtube_error_response)?;
+    let id = save_channel(&state.pool, &channel, tracked)
+        .await
+        .map_err(database_error_response)?;
+
+    // Only turning tracking off, so there's nothing to queue
+    if !tracked {
+        return Ok(Json(CreateChannelResponse {
+            success: true,
+            id,
+            youtube_id: channel.youtube_id,
+            title: channel.title,
+            tracked,
+            queued: 0,
+            skipped: 0,
+            videos: vec![],
+        }));
+    }
+
+    let videos = sync_channel(
+        &state.pool,
+        state.youtube.as_ref(),
+        &state.jobs,
+        id,
+        &channel.uploads_playlist_id,
+        &options,
+    )
+    .await
+    .map_err(ingest_error_response)?;
+
+    let queued = videos.iter().filter(|v| v.job_id.is_some()).count();
+
+    Ok(Json(CreateChannelResponse {
+        success: true,
+        id,
+        youtube_id: channel.youtube_id,
+        title: channel.title,
+        tracked,
+        queued,
+        skipped: videos.len() - queued,
+        videos,
+    }))
+}
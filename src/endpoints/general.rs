@@ -1,10 +1,20 @@
+use crate::ingest::IngestError;
+use crate::jobs::JobQueue;
+use crate::quota::Quota;
+use crate::youtube::{YouTubeApi, YouTubeApiError};
 use rocket::get;
+use rocket::http::Status;
+use rocket::response::status;
 use rocket::serde::json::Json;
 use rocket::serde::Serialize;
 use sqlx::PgPool;
+use std::sync::Arc;
 
 pub struct ApiState {
     pub pool: PgPool,
+    pub youtube: Arc<dyn YouTubeApi>,
+    pub jobs: JobQueue,
+    pub quota: Quota,
 }
 
 #[derive(Debug, Serialize)]
@@ -12,6 +22,62 @@ pub struct SuccessFailResponse {
     pub success: bool,
 }
 
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub error: String,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(error: &str, message: String) -> ErrorResponse {
+        ErrorResponse {
+            success: false,
+            error: error.to_string(),
+            message,
+        }
+    }
+}
+
+pub fn database_error_response(e: sqlx::Error) -> status::Custom<Json<ErrorResponse>> {
+    status::Custom(
+        Status::InternalServerError,
+        Json(ErrorResponse::new("database_error", e.to_string())),
+    )
+}
+
+// For Data API calls made while handling a request, e.g. looking up a
+// playlist's videos
+pub fn youtube_error_response(e: YouTubeApiError) -> status::Custom<Json<ErrorResponse>> {
+    let status = match e {
+        YouTubeApiError::QuotaTracking(e) => return database_error_response(e),
+        YouTubeApiError::VideoNotFound(_)
+        | YouTubeApiError::ChannelNotFound(_)
+        | YouTubeApiError::PlaylistNotFound(_) => Status::NotFound,
+        // Nothing the caller can do about either of these
+        YouTubeApiError::QuotaExceeded(_) | YouTubeApiError::BudgetExhausted(_) => {
+            Status::ServiceUnavailable
+        }
+        YouTubeApiError::InvalidKey(_) => Status::InternalServerError,
+        _ => Status::BadGateway,
+    };
+
+    status::Custom(status, Json(ErrorResponse::new(e.code(), e.to_string())))
+}
+
+// For ingestion errors that happen while handling a request (rather than in a
+// job)
+pub fn ingest_error_response(e: IngestError) -> status::Custom<Json<ErrorResponse>> {
+    match e {
+        IngestError::Database(e) => database_error_response(e),
+        IngestError::YouTube(e) => youtube_error_response(e),
+        _ => status::Custom(
+            Status::BadGateway,
+            Json(ErrorResponse::new(e.code(), e.to_string())),
+        ),
+    }
+}
+
 #[get("/")]
 pub fn index() -> Json<SuccessFailResponse> {
     Json(SuccessFailResponse { success: true })
@@ -0,0 +1,32 @@
+use super::general::{database_error_response, ApiState, ErrorResponse};
+use crate::jobs::Job;
+use rocket::get;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+// Reports how a queued ingestion is getting on: queued, running, succeeded
+// (with the video id) or failed (with the error)
+#[get("/job/<id>")]
+pub async fn get_job(
+    id: i32,
+    state: &State<ApiState>,
+) -> Result<Json<Job>, status::Custom<Json<ErrorResponse>>> {
+    let job = state
+        .jobs
+        .get(id)
+        .await
+        .map_err(database_error_response)?
+        .ok_or_else(|| {
+            status::Custom(
+                Status::NotFound,
+                Json(ErrorResponse::new(
+                    "job_not_found",
+                    format!("No job with id {id}"),
+                )),
+            )
+        })?;
+
+    Ok(Json(job))
+}
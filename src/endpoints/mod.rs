@@ -1,3 +1,8 @@
+pub mod admin;
+pub mod channels;
 pub mod general;
+pub mod jobs;
+pub mod playlists;
+pub mod transcripts;
 pub mod users;
 pub mod videos;
@@ -0,0 +1,74 @@
+use super::general::{database_error_response, youtube_error_response, ApiState, ErrorResponse};
+use crate::ingest::IngestOptions;
+use crate::jobs::QueuedVideo;
+use crate::utils::captions::{default_caption_preferences, CaptionPreference};
+use crate::utils::youtube_url::parse_playlist_id;
+use rocket::http::Status;
+use rocket::post;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+#[derive(Debug, Deserialize)]
+pub struct NewPlaylistUrl {
+    // A playlist url, a watch url with `list=`, or just the playlist id
+    pub url: String,
+    pub caption_preferences: Option<Vec<CaptionPreference>>,
+    // Re-ingest videos we've already indexed instead of skipping them
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatePlaylistResponse {
+    pub success: bool,
+    pub playlist_id: String,
+    pub queued: usize,
+    pub skipped: usize,
+    pub videos: Vec<QueuedVideo>,
+}
+
+// Queues every video in a playlist for ingestion, the same way `POST /video`
+// does for a single video
+#[post("/playlist", data = "<playlist_url>")]
+pub async fn create_playlist(
+    playlist_url: Json<NewPlaylistUrl>,
+    state: &State<ApiState>,
+) -> Result<Json<CreatePlaylistResponse>, status::Custom<Json<ErrorResponse>>> {
+    let playlist_id = parse_playlist_id(&playlist_url.url).map_err(|e| {
+        status::Custom(
+            Status::BadRequest,
+            Json(ErrorResponse::new("invalid_url", e.to_string())),
+        )
+    })?;
+
+    let options = IngestOptions {
+        caption_preferences: playlist_url
+            .caption_preferences
+            .clone()
+            .unwrap_or_else(default_caption_preferences),
+        refresh: playlist_url.refresh.unwrap_or(false),
+    };
+
+    let youtube_ids = state
+        .youtube
+        .playlist_video_ids(&playlist_id)
+        .await
+        .map_err(youtube_error_response)?;
+
+    let videos = state
+        .jobs
+        .enqueue_videos(youtube_ids, &options)
+        .await
+        .map_err(database_error_response)?;
+
+    let queued = videos.iter().filter(|v| v.job_id.is_some()).count();
+
+    Ok(Json(CreatePlaylistResponse {
+        success: true,
+        playlist_id,
+        queued,
+        skipped: videos.len() - queued,
+        videos,
+    }))
+}
@@ -0,0 +1,182 @@
+use super::general::{database_error_response, ApiState, ErrorResponse};
+use crate::ingest::{delete_caption_sets, insert_caption_set};
+use crate::utils::captions::{CaptionKind, FetchedCaptions, YouTubeCaptionTextSnippet};
+use crate::utils::transcripts::{
+    parse_transcript, render_transcript, ExportFormat, TranscriptFormat,
+};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket::{get, post};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportTranscriptResponse {
+    pub success: bool,
+    pub caption_id: i32,
+    pub cues: usize,
+    // Whether an earlier import in the same language was replaced
+    pub replaced: bool,
+}
+
+fn bad_request(error: &str, message: String) -> status::Custom<Json<ErrorResponse>> {
+    status::Custom(Status::BadRequest, Json(ErrorResponse::new(error, message)))
+}
+
+// Imports an srt or vtt transcript for a video we've already ingested. The
+// format is detected from the file when `format` isn't given. Importing again
+// in the same language replaces the previous import. Search and export use
+// an imported transcript over any of YouTube's tracks in the same language.
+#[post("/video/<id>/transcript?<format>&<lang>", data = "<transcript>")]
+pub async fn import_transcript(
+    id: i32,
+    format: Option<&str>,
+    lang: Option<&str>,
+    transcript: Data<'_>,
+    state: &State<ApiState>,
+) -> Result<Json<ImportTranscriptResponse>, status::Custom<Json<ErrorResponse>>> {
+    let transcript = transcript
+        .open(10.mebibytes())
+        .into_string()
+        .await
+        .map_err(|e| bad_request("invalid_transcript", e.to_string()))?;
+    if !transcript.is_complete() {
+        return Err(bad_request(
+            "transcript_too_large",
+            "Transcripts must be under 10MiB".to_string(),
+        ));
+    }
+
+    let format = match format {
+        Some(name) => TranscriptFormat::from_name(name).ok_or_else(|| {
+            bad_request(
+                "unknown_format",
+                format!("Unknown transcript format \"{name}\", expected srt or vtt"),
+            )
+        })?,
+        None => TranscriptFormat::detect(&transcript),
+    };
+    let captions = parse_transcript(&transcript, format)
+        .map_err(|e| bad_request("invalid_transcript", e.to_string()))?;
+
+    let caption_set = FetchedCaptions {
+        language: lang.unwrap_or("en").trim().to_string(),
+        kind: CaptionKind::Imported,
+        captions,
+    };
+
+    let mut tx = state.pool.begin().await.map_err(database_error_response)?;
+
+    let video: Option<i32> = sqlx::query_scalar("select id from videos where id=$1")
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(database_error_response)?;
+    if video.is_none() {
+        return Err(status::Custom(
+            Status::NotFound,
+            Json(ErrorResponse::new(
+                "video_not_found",
+                format!("No video with id {id}"),
+            )),
+        ));
+    }
+
+    let existing_caption_ids: Vec<i32> =
+        sqlx::query_scalar("select id from captions where video_id=$1 and language=$2 and kind=$3")
+            .bind(id)
+            .bind(&caption_set.language)
+            .bind(caption_set.kind.as_str())
+            .fetch_all(&mut tx)
+            .await
+            .map_err(database_error_response)?;
+
+    delete_caption_sets(&mut tx, &existing_caption_ids)
+        .await
+        .map_err(database_error_response)?;
+
+    let caption_id = insert_caption_set(&mut tx, id, &caption_set)
+        .await
+        .map_err(database_error_response)?;
+
+    tx.commit().await.map_err(database_error_response)?;
+
+    Ok(Json(ImportTranscriptResponse {
+        success: true,
+        caption_id,
+        cues: caption_set.captions.len(),
+        replaced: !existing_caption_ids.is_empty(),
+    }))
+}
+
+// Hands out a video's transcript as srt, vtt, plain text or json. When a video
+// has several caption sets in the language we use the same one search does.
+#[get("/video/<id>/transcript?<format>&<lang>")]
+pub async fn export_transcript(
+    id: i32,
+    format: Option<&str>,
+    lang: Option<&str>,
+    state: &State<ApiState>,
+) -> Result<(ContentType, String), status::Custom<Json<ErrorResponse>>> {
+    let format_name = format.unwrap_or("srt");
+    let format = ExportFormat::from_name(format_name).ok_or_else(|| {
+        bad_request(
+            "unknown_format",
+            format!("Unknown transcript format \"{format_name}\", expected srt, vtt, txt or json"),
+        )
+    })?;
+    let language = lang.unwrap_or("en").to_lowercase();
+    let language = language.split('-').next().unwrap_or_default();
+
+    let caption_id = sqlx::query_scalar!(
+        "
+        select id from captions
+        where video_id = $1
+        and split_part(lower(language), '-', 1) = $2
+        order by coalesce(kind = 'imported', false) desc, coalesce(kind = 'asr', false), id
+        limit 1",
+        id,
+        language,
+    )
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(database_error_response)?
+    .ok_or_else(|| {
+        status::Custom(
+            Status::NotFound,
+            Json(ErrorResponse::new(
+                "transcript_not_found",
+                format!("Video {id} has no transcript in \"{language}\""),
+            )),
+        )
+    })?;
+
+    let rows = sqlx::query!(
+        "select caption_text, start, duration from caption_timestamps where caption_id = $1 order by start, id",
+        caption_id,
+    )
+    .fetch_all(&state.pool)
+    .await
+    .map_err(database_error_response)?;
+
+    let captions: Vec<YouTubeCaptionTextSnippet> = rows
+        .into_iter()
+        .map(|row| YouTubeCaptionTextSnippet {
+            text: row.caption_text,
+            start: row.start as f32,
+            duration: row.duration as f32,
+            words: None,
+        })
+        .collect();
+
+    let content_type = match format {
+        ExportFormat::Srt => ContentType::new("application", "x-subrip"),
+        ExportFormat::Vtt => ContentType::new("text", "vtt"),
+        ExportFormat::Txt => ContentType::Plain,
+        ExportFormat::Json => ContentType::JSON,
+    };
+
+    Ok((content_type, render_transcript(&captions, format)))
+}
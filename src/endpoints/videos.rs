@@ -1,17 +1,20 @@
 use crate::endpoints::general::ApiState;
-use crate::utils::captions::fetch_captions;
-use crate::utils::environment::get_env;
+use crate::ingest::IngestOptions;
+use crate::utils::captions::{default_caption_preferences, text_search_config, CaptionPreference};
+use crate::utils::search_query::{check_search_query, SearchQueryError};
+use crate::utils::youtube_url::parse_video_id;
 use chrono::serde::ts_seconds_option;
 use chrono::{DateTime, Utc};
-use querystring::querify;
+use rocket::http::Status;
+use rocket::response::status;
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use rocket::State;
 use rocket::{get, post};
-use sqlx::{Error, FromRow};
-use url::Url;
+use sqlx::{FromRow, PgPool};
+use std::collections::{HashMap, HashSet};
 
-use super::general::SuccessFailResponse;
+use super::general::{database_error_response, ErrorResponse, SuccessFailResponse};
 
 #[derive(Debug, Clone, Deserialize, FromRow, Serialize)]
 pub struct Video {
@@ -33,7 +36,12 @@ pub struct Video {
 pub async fn get_videos(state: &State<ApiState>) -> Json<Option<Vec<Video>>> {
     let videos = sqlx::query_as::<_, Video>(
         "select v.id, v.channel_id, ch.title as channel_title, v.title, v.url, LEFT(ca.raw_text, 400) as captions, v.upload_datetime, v.views, v.length, v.thumbnail, v.youtube_id from videos v
-        join captions ca on ca.video_id=v.id
+        join lateral (
+            select raw_text from captions
+            where video_id=v.id
+            order by split_part(language, '-', 1)='en' desc, coalesce(kind='imported', false) desc, coalesce(kind='asr', false), id
+            limit 1
+        ) ca on true
         join channels ch on ch.id=v.channel_id
         limit 50",
     )
@@ -51,355 +59,626 @@ pub async fn get_videos(state: &State<ApiState>) -> Json<Option<Vec<Video>>> {
 #[derive(Debug, Deserialize)]
 pub struct NewVideoUrl {
     pub url: String,
+    // Which caption track to ingest, in order of preference
+    pub caption_preferences: Option<Vec<CaptionPreference>>,
+    // Re-fetch the details and captions of a video we've already ingested
+    pub refresh: Option<bool>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeVideoResponse {
-    items: Vec<YouTubeVideoItem>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeVideoItem {
-    id: String,
-    snippet: YouTubeVideoSnippet,
-    statistics: YouTubeVideoStatistics,
-    #[serde(rename = "contentDetails")]
-    content_details: YouTubeVideoContentDetails,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeVideoSnippet {
-    #[serde(rename = "publishedAt")]
-    published_at: String,
-    #[serde(rename = "channelId")]
-    channel_id: String,
-    title: String,
-    thumbnails: YouTubeVideoThumbnailTypes,
-    #[serde(rename = "channelTitle")]
-    channel_title: String,
+pub struct CreateVideoResponse {
+    pub success: bool,
+    // Poll `GET /job/<id>` to find out how ingestion went
+    pub job_id: i32,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeVideoContentDetails {
-    duration: String,
-}
+#[post("/video", data = "<video_url>")]
+pub async fn create_video(
+    video_url: Json<NewVideoUrl>,
+    state: &State<ApiState>,
+) -> Result<Json<CreateVideoResponse>, status::Custom<Json<ErrorResponse>>> {
+    let youtube_video_id = parse_video_id(&video_url.url).map_err(|e| {
+        status::Custom(
+            Status::BadRequest,
+            Json(ErrorResponse::new("invalid_url", e.to_string())),
+        )
+    })?;
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeVideoThumbnailTypes {
-    default: YouTubeVideoThumbnail,
-}
+    let options = IngestOptions {
+        caption_preferences: video_url
+            .caption_preferences
+            .clone()
+            .unwrap_or_else(default_caption_preferences),
+        refresh: video_url.refresh.unwrap_or(false),
+    };
+    let job_id = state
+        .jobs
+        .enqueue_video(&youtube_video_id, &options)
+        .await
+        .map_err(database_error_response)?;
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeVideoThumbnail {
-    url: String,
+    Ok(Json(CreateVideoResponse {
+        success: true,
+        job_id,
+    }))
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeVideoStatistics {
-    #[serde(rename = "viewCount")]
-    view_count: String,
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CaptionTextSnippet {
+    pub url: String,
+    pub caption_text: String,
+    // HTML: `caption_text` escaped, with the words that matched wrapped in the
+    // highlight markers (which go in as they are), going by the same stemming
+    // as the search itself
+    pub highlighted: String,
+    pub start: f64,
+    // Which of the video's `context` passages the hit is in, when the search
+    // asked for context
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passage: Option<usize>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct CreateVideoResponse {
+// A struct for "bucketing" together caption snippets into the same video
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CaptionSearchResults {
     pub success: bool,
-    pub id: i32,
+    pub videos: Vec<VideoCaptionsResult>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeChannelResponse {
-    items: Vec<YouTubeChannelItem>,
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct VideoCaptionsResult {
+    pub video: Video,
+    // How well the video matched the search; higher is better
+    pub score: f64,
+    pub captions: Vec<CaptionTextSnippet>,
+    // Only filled in when the search asked for `context`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context: Vec<CaptionContext>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeChannelItem {
-    id: String,
-    snippet: YouTubeChannelSnippet,
+// A run of consecutive caption lines around one or more hits. Hits close
+// enough together for their context to overlap share a passage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptionContext {
+    pub start: f64,
+    pub end: f64,
+    pub lines: Vec<ContextLine>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeChannelSnippet {
-    title: String,
-    thumbnails: YouTubeChannelThumbnailTypes,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContextLine {
+    pub caption_text: String,
+    pub start: f64,
+    pub duration: f64,
+    // Whether this line is one of the hits
+    pub hit: bool,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeChannelThumbnailTypes {
-    default: YouTubeChannelThumbnail,
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct VideoCaptionBuckets {
+    pub video: Video,
+    pub captions: Vec<CaptionTextSnippet>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct YouTubeChannelThumbnail {
-    url: String,
+// How to order videos in search results. Captions within a video are always
+// in the order they're said.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchSort {
+    // Best matches first, going by `VideoCaptionsResult.score`
+    Relevance,
+    Newest,
+    Oldest,
+    Views,
 }
 
-#[derive(Debug, FromRow, Serialize)]
-struct RowId {
-    id: i32,
+impl SearchSort {
+    pub fn parse(s: &str) -> Option<SearchSort> {
+        match s {
+            "relevance" => Some(SearchSort::Relevance),
+            "newest" => Some(SearchSort::Newest),
+            "oldest" => Some(SearchSort::Oldest),
+            "views" => Some(SearchSort::Views),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SearchSort::Relevance => "relevance",
+            SearchSort::Newest => "newest",
+            SearchSort::Oldest => "oldest",
+            SearchSort::Views => "views",
+        }
+    }
 }
 
-#[post("/video", data = "<video_url>")]
-pub async fn create_video(
-    video_url: Json<NewVideoUrl>,
+#[get("/video/caption/search?<text>&<lang>&<sort>&<highlight_start>&<highlight_stop>&<context>")]
+pub async fn search_video_captions(
+    text: &str,
+    lang: Option<&str>,
+    sort: Option<&str>,
+    highlight_start: Option<&str>,
+    highlight_stop: Option<&str>,
+    context: Option<u32>,
     state: &State<ApiState>,
-) -> Json<CreateVideoResponse> {
-    let url = video_url.url.clone();
-    let youtube_video_id: String;
-
-    // Grab the id out of the url string Urls can be in either of the following formats:
-    // (1) https://youtu.be/TTjYjSEGHek
-    // (2) https://www.youtube.com/watch?v=TTjYjSEGHek
-    if url.contains("youtu.be") {
-        youtube_video_id = Url::parse(&url).unwrap().path().split_at(1).1.to_string();
-    } else {
-        let parsed_url = Url::parse(&url).unwrap();
-        let qs = parsed_url.query().unwrap();
-        let qs_parts = querify(qs);
-        let temp = qs_parts
-            .iter()
-            .find(|&&q| q.0 == "v")
-            .unwrap()
-            .1
-            .to_string();
-        youtube_video_id = temp;
+) -> Result<Json<CaptionSearchResults>, status::Custom<Json<ErrorResponse>>> {
+    let sort = match sort {
+        None => SearchSort::Relevance,
+        Some(sort) => SearchSort::parse(sort).ok_or_else(|| {
+            status::Custom(
+                Status::BadRequest,
+                Json(ErrorResponse::new(
+                    "invalid_sort",
+                    format!("Unknown sort \"{sort}\". Use relevance, newest, oldest or views"),
+                )),
+            )
+        })?,
+    };
+
+    let context = context.unwrap_or(0);
+    if context > MAX_CONTEXT {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Json(ErrorResponse::new(
+                "invalid_context",
+                format!("context can be at most {MAX_CONTEXT} lines"),
+            )),
+        ));
     }
 
-    let youtube_api_key = get_env("YOUTUBE_API_KEY");
+    let defaults = SearchOptions::default();
+    let options = SearchOptions {
+        lang: lang.map(str::to_string).unwrap_or(defaults.lang),
+        sort,
+        highlight_start: highlight_start
+            .map(str::to_string)
+            .unwrap_or(defaults.highlight_start),
+        highlight_stop: highlight_stop
+            .map(str::to_string)
+            .unwrap_or(defaults.highlight_stop),
+        context,
+    };
 
-    let youtube_api_url = format!("https://www.googleapis.com/youtube/v3/videos?key={youtube_api_key}&part=id,snippet,statistics,contentDetails&id={youtube_video_id}");
-    let video = reqwest::get(youtube_api_url)
-        .await
-        .unwrap()
-        .json::<YouTubeVideoResponse>()
+    let videos = search_captions(&state.pool, text, &options)
         .await
-        .unwrap();
-
-    let video_to_insert: YouTubeVideoItem = video.items[0].clone();
-    let channel_youtube_id = video_to_insert.snippet.channel_id.clone();
-
-    // Check channels table if channel id already exists
-    let row = sqlx::query_as::<_, RowId>("select id from channels where youtube_id=$1")
-        .bind(&channel_youtube_id)
-        .fetch_one(&state.pool)
-        .await;
-
-    let mut channel_id: i32 = -1;
-    let youtube_api_key = get_env("YOUTUBE_API_KEY");
+        .map_err(|e| match e {
+            SearchError::Query(e) => status::Custom(
+                Status::BadRequest,
+                Json(ErrorResponse::new("invalid_query", e.to_string())),
+            ),
+            SearchError::Database(e) => database_error_response(e),
+        })?;
 
-    if let Err(_) = row {
-        // Fetch the channel details, and insert them into the channels table
-        let youtube_api_channel_url = format!("https://www.googleapis.com/youtube/v3/channels?key={youtube_api_key}&part=id,snippet,statistics,contentDetails&id={channel_youtube_id}");
-        let channel = reqwest::get(&youtube_api_channel_url)
-            .await
-            .unwrap()
-            .json::<YouTubeChannelResponse>()
-            .await
-            .unwrap();
-
-        dbg!(&channel);
-        let channel_to_insert = channel.items[0].clone();
-
-        let result: Result<i32, Error> =
-        sqlx::query_scalar("insert into channels (title, url, thumbnail, youtube_id) values ($1, $2, $3, $4) returning id")
-            .bind(channel_to_insert.snippet.title)
-            .bind(format!("https://youtube.com/channel/{}", channel_to_insert.id))
-            .bind(channel_to_insert.snippet.thumbnails.default.url)
-            .bind(channel_to_insert.id)
-            .fetch_one(&state.pool)
-            .await;
-
-        channel_id = result.unwrap();
-    } else if let Ok(r) = row {
-        channel_id = r.id;
-    }
+    Ok(Json(CaptionSearchResults {
+        success: true,
+        videos,
+    }))
+}
 
-    let result: Result<i32, Error> =
-        sqlx::query_scalar("insert into videos (channel_id, title, url, upload_datetime, views, length, thumbnail, youtube_id) values ($1, $2, $3, $4, $5, $6, $7, $8) returning id")
-            .bind(channel_id)
-            .bind(video_to_insert.snippet.title)
-            .bind(video_url.url.clone())
-            // .bind(video_to_insert.snippet.published_at)
-            .bind(Utc::now())
-            .bind(video_to_insert.statistics.view_count.parse::<i64>().unwrap())
-            .bind(30)
-            // .bind(video_to_insert.content_details.duration)
-            .bind(video_to_insert.snippet.thumbnails.default.url)
-            .bind(youtube_video_id.clone())
-            .fetch_one(&state.pool)
-            .await;
-
-    let video_id = result.unwrap();
-    let video_captions = fetch_captions(youtube_video_id.clone()).await;
-    let raw_text = video_captions
-        .iter()
-        .fold(String::new(), |acc, s| acc + &s.text + " ");
-    let caption_id_result: Result<i32, Error> = sqlx::query_scalar(
-        "insert into captions (video_id, raw_text, caption_json) values ($1, $2, $3) returning id",
-    )
-    .bind(video_id)
-    .bind(raw_text)
-    .bind(sqlx::types::Json(&video_captions))
-    .fetch_one(&state.pool)
-    .await;
+// `ts_headline` options that highlight every match in the whole line. Values
+// are quoted (doubling any quotes inside) so markers can hold anything.
+fn headline_options(start: &str, stop: &str) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
 
-    dbg!(&video_captions);
-
-    let caption_id = caption_id_result.unwrap();
-    dbg!(caption_id);
-
-    let video_ids = video_captions
-        .iter()
-        .map(|_c| video_id)
-        .collect::<Vec<i32>>();
-    let caption_ids = video_captions
-        .iter()
-        .map(|_c| caption_id)
-        .collect::<Vec<i32>>();
-    let caption_texts = video_captions
-        .iter()
-        .map(|c| c.text.clone())
-        .collect::<Vec<String>>();
-    let caption_starts = video_captions.iter().map(|c| c.start).collect::<Vec<f32>>();
-    let caption_durations = video_captions
-        .iter()
-        .map(|c| c.duration)
-        .collect::<Vec<f32>>();
-
-    let caption_timestamp_result: Result<i32, Error> = sqlx::query_scalar(
-        "insert into caption_timestamps (video_id, caption_id, caption_text, start, duration) select * from unnest($1, $2, $3, $4, $5) returning id",
+    format!(
+        "StartSel={}, StopSel={}, HighlightAll=true",
+        quote(start),
+        quote(stop)
     )
-        .bind(video_ids)
-        .bind(caption_ids)
-        .bind(caption_texts)
-        .bind(caption_starts)
-        .bind(caption_durations)
-        .fetch_one(&state.pool)
-        .await;
-
-    dbg!(caption_timestamp_result.unwrap());
+}
 
-    Json(CreateVideoResponse {
-        success: true,
-        id: video_id,
-    })
+#[derive(Debug)]
+pub enum SearchError {
+    Query(SearchQueryError),
+    Database(sqlx::Error),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct CaptionTextSnippet {
-    pub url: String,
-    pub caption_text: String,
-    pub start: f64,
+impl From<SearchQueryError> for SearchError {
+    fn from(e: SearchQueryError) -> Self {
+        SearchError::Query(e)
+    }
 }
 
-// A struct for "bucketing" together caption snippets into the same video
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct CaptionSearchResults {
-    pub success: bool,
-    pub videos: Vec<VideoCaptionsResult>,
+impl From<sqlx::Error> for SearchError {
+    fn from(e: sqlx::Error) -> Self {
+        SearchError::Database(e)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct VideoCaptionsResult {
-    pub video: Video,
-    pub captions: Vec<CaptionTextSnippet>,
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub lang: String,
+    pub sort: SearchSort,
+    // Put either side of each matched word in `CaptionTextSnippet.highlighted`
+    pub highlight_start: String,
+    pub highlight_stop: String,
+    // How many lines either side of each hit to return in
+    // `VideoCaptionsResult.context`
+    pub context: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct VideoCaptionBuckets {
-    pub video: Video,
-    pub captions: Vec<CaptionTextSnippet>,
+// The most `context` lines we'll return either side of a hit
+const MAX_CONTEXT: u32 = 20;
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            lang: "en".to_string(),
+            sort: SearchSort::Relevance,
+            highlight_start: "<b>".to_string(),
+            highlight_stop: "</b>".to_string(),
+            context: 0,
+        }
+    }
 }
 
-#[get("/video/caption/search?<text>")]
-pub async fn search_video_captions(
+// `text` takes quoted phrases, `-exclusions` and `OR` (see
+// `utils::search_query`)
+pub async fn search_captions(
+    pool: &PgPool,
     text: &str,
-    state: &State<ApiState>,
-) -> Json<Option<CaptionSearchResults>> {
-    // If the user searches for text with spaces in it, such as "tennis match",
-    // then we want to find any row that contains the text "tennis" AND "match".
-    // To do this we put a `&` character inbetween every word.
-    let search_text = text.replace(" ", " & ");
+    options: &SearchOptions,
+) -> Result<Vec<VideoCaptionsResult>, SearchError> {
+    check_search_query(text)?;
+
+    // Only search the captions in the requested language, stemmed the same way
+    // they were indexed. A video can have both a manual and an ASR track in
+    // the same language, in which case we only search the manual one.
+    let language = options.lang.to_lowercase();
+    let ts_config = text_search_config(&language);
+    let language = language.split('-').next().unwrap_or_default().to_string();
+
+    // Stop words are dropped from the query, which can leave nothing at all,
+    // or only exclusions: "the -tennis" becomes just "not tennis". A query
+    // that would match a line with no words in it matches nearly every line.
+    let (nodes, matches_empty_line): (i32, bool) = sqlx::query_as(
+        "select numnode(q), ''::tsvector @@ q from websearch_to_tsquery($1::text::regconfig, $2) q",
+    )
+    .bind(ts_config)
+    .bind(text)
+    .fetch_one(pool)
+    .await?;
+    if nodes == 0 {
+        return Err(SearchQueryError::NoSearchableWords.into());
+    }
+    if matches_empty_line {
+        return Err(SearchQueryError::OnlyExclusions.into());
+    }
 
+    // Caption text comes from YouTube and from uploaded transcripts, so it's
+    // HTML escaped before `ts_headline` marks it up. Entities like `&lt;` are
+    // left alone by the text search parser.
+    //
+    // Phrases said across two lines are found through `caption_windows`, which
+    // pairs each line with the next. Those hits show both lines, and only
+    // count when neither line matches by itself.
+    //
+    // When we have word timings for a matching line, `start` (and the link) point
+    // at the first word of the earliest match rather than the start of the
+    // line: the words up to the first point the query matches, trimmed from the
+    // front for as long as they still match. Only words that are one of the
+    // query's (non-excluded) terms are considered. Lines without word timings
+    // link to a couple of seconds before the line instead.
+    //
+    // A video's score is its best matching line's `ts_rank_cd`, plus up to 0.1
+    // for how densely it matches (matching lines per minute, capped at one) so
+    // that a video about the search terms beats one that mentions them once.
     let rows = sqlx::query!(
         "
+        with query as (
+            select
+                websearch_to_tsquery($2::text::regconfig, $1) as query,
+                array(
+                    select replace(l[1], '''''', '''')
+                    from regexp_matches(querytree(websearch_to_tsquery($2::text::regconfig, $1)), '''((?:[^'']|'''')*)''', 'g') l
+                ) as lexemes
+        ),
+        tracks as (
+            select distinct on (ca.video_id) ca.id from captions ca
+            where split_part(lower(ca.language), '-', 1) = $3
+            order by ca.video_id, coalesce(ca.kind = 'imported', false) desc, coalesce(ca.kind = 'asr', false), ca.id
+        ),
+        matches as (
+            select
+                ct.video_id,
+                ct.caption_text,
+                ct.start,
+                ct.ts_config,
+                array[ct.id] as line_ids,
+                ts_rank_cd(to_tsvector(ct.ts_config, ct.caption_text), websearch_to_tsquery($2::text::regconfig, $1)) as rank
+            from caption_timestamps ct
+            where to_tsvector(ct.ts_config, ct.caption_text) @@ websearch_to_tsquery($2::text::regconfig, $1)
+            and ct.caption_id in (select id from tracks)
+            union all
+            select
+                win.video_id,
+                win.window_text,
+                ct.start,
+                win.ts_config,
+                array[ct.id, nt.id],
+                ts_rank_cd(to_tsvector(win.ts_config, win.window_text), websearch_to_tsquery($2::text::regconfig, $1))
+            from caption_windows win
+            join caption_timestamps ct on ct.id = win.caption_timestamp_id
+            join caption_timestamps nt on nt.id = win.next_caption_timestamp_id
+            where to_tsvector(win.ts_config, win.window_text) @@ websearch_to_tsquery($2::text::regconfig, $1)
+            and win.caption_id in (select id from tracks)
+            and not to_tsvector(ct.ts_config, ct.caption_text) @@ websearch_to_tsquery($2::text::regconfig, $1)
+            and not to_tsvector(nt.ts_config, nt.caption_text) @@ websearch_to_tsquery($2::text::regconfig, $1)
+        ),
+        hits as (
+            select
+                v.id,
+                v.channel_id,
+                v.title,
+                v.upload_datetime,
+                v.views,
+                v.length,
+                v.thumbnail,
+                v.youtube_id,
+                CONCAT('https://www.youtube.com/watch?v=', v.youtube_id) as base_url,
+                ch.title as channel_title,
+                CONCAT('https://www.youtube.com/watch?v=', v.youtube_id, '&t=', GREATEST(COALESCE(FLOOR(w.start)::integer, m.start::integer - 2), 0), 's') as url,
+                m.caption_text,
+                ts_headline(
+                    m.ts_config,
+                    replace(replace(replace(replace(replace(m.caption_text, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '\"', '&quot;'), '''', '&#39;'),
+                    websearch_to_tsquery($2::text::regconfig, $1),
+                    $5
+                ) as highlighted,
+                COALESCE(w.start, m.start) as start,
+                m.line_ids,
+                m.rank
+            from matches m
+            join videos v on v.id = m.video_id
+            join channels ch on ch.id=v.channel_id
+            cross join query q
+            left join lateral (
+                with words as (
+                    select cw.start, cw.word, row_number() over (order by cw.start, cw.id) as i
+                    from caption_words cw
+                    where cw.caption_timestamp_id = any(m.line_ids)
+                ),
+                candidates as (
+                    select * from words
+                    where tsvector_to_array(to_tsvector(m.ts_config, word)) && q.lexemes
+                ),
+                first_end as (
+                    select min(e.i) as i from candidates e
+                    where to_tsvector(m.ts_config, (select string_agg(p.word, ' ' order by p.i) from words p where p.i <= e.i)) @@ q.query
+                )
+                select s.start from candidates s, first_end
+                where s.i <= first_end.i
+                and to_tsvector(m.ts_config, (select string_agg(p.word, ' ' order by p.i) from words p where p.i between s.i and first_end.i)) @@ q.query
+                order by s.i desc
+                limit 1
+            ) w on true
+        ),
+        scored as (
+            select
+                hits.*,
+                max(rank) over video + 0.1 * least(count(*) over video / greatest(length / 60.0, 1), 1) as score
+            from hits
+            window video as (partition by id)
+        )
         select
-            v.id,
-            v.channel_id,
-            v.title,
-            v.upload_datetime,
-            v.views,
-            v.length,
-            v.thumbnail,
-            v.youtube_id,
-            CONCAT('https://www.youtube.com/watch?v=', v.youtube_id) as base_url,
-            ch.title as channel_title,
-            CONCAT('https://www.youtube.com/watch?v=', v.youtube_id, '&t=', GREATEST(ct.start::integer - 2, 0), 's') as url,
-            ct.caption_text,
-            ct.start
-        from caption_timestamps ct
-        join videos v on v.id = ct.video_id
-        join channels ch on ch.id=v.channel_id
-        where to_tsvector('english', caption_text) @@ to_tsquery('english', $1)
-        order by v.upload_datetime desc, ct.start",
-        search_text,
+            id as \"id!\",
+            channel_id as \"channel_id!\",
+            title as \"title!\",
+            upload_datetime as \"upload_datetime!\",
+            views as \"views!\",
+            length as \"length!\",
+            thumbnail as \"thumbnail!\",
+            youtube_id as \"youtube_id!\",
+            base_url as \"base_url!\",
+            channel_title as \"channel_title!\",
+            url as \"url!\",
+            caption_text as \"caption_text!\",
+            highlighted as \"highlighted!\",
+            line_ids as \"line_ids!\",
+            start as \"start!\",
+            score::float8 as \"score!\"
+        from scored
+        order by
+            case when $4 = 'relevance' then score end desc,
+            case when $4 = 'oldest' then upload_datetime end asc,
+            case when $4 = 'views' then views end desc,
+            upload_datetime desc,
+            id,
+            start",
+        text,
+        ts_config,
+        language,
+        options.sort.as_str(),
+        headline_options(&options.highlight_start, &options.highlight_stop),
     )
-    .fetch_all(&state.pool)
-    .await
-    .unwrap();
+    .fetch_all(pool)
+    .await?;
 
     let mut videos: Vec<VideoCaptionsResult> = vec![];
-    let mut temp_video: Video = Video {
-        id: 0,
-        channel_id: 0,
-        channel_title: String::new(),
-        title: String::new(),
-        url: String::new(),
-        captions: String::new(),
-        upload_datetime: None,
-        views: 0,
-        length: 0,
-        thumbnail: String::new(),
-        youtube_id: String::new(),
-    };
+    // The lines each hit covers, in the same order as the hits
+    let mut hit_line_ids: Vec<Vec<i32>> = vec![];
 
     for row in rows {
         // Each row effectively stores all the data for each video already, so
         // we just create the video once
-        if temp_video.id != row.id {
-            temp_video = Video {
-                id: row.id,
-                channel_id: row.channel_id,
-                channel_title: row.channel_title,
-                title: row.title,
-                url: row.base_url.unwrap(),
-                captions: String::new(),
-                upload_datetime: Some(row.upload_datetime),
-                views: row.views,
-                length: row.length,
-                thumbnail: row.thumbnail,
-                youtube_id: row.youtube_id,
-            };
-
-            let temp_captions: Vec<CaptionTextSnippet> = vec![];
-
+        if videos.last().map(|v| v.video.id) != Some(row.id) {
             videos.push(VideoCaptionsResult {
-                video: temp_video.clone(),
-                captions: temp_captions,
+                video: Video {
+                    id: row.id,
+                    channel_id: row.channel_id,
+                    channel_title: row.channel_title,
+                    title: row.title,
+                    url: row.base_url,
+                    captions: String::new(),
+                    upload_datetime: Some(row.upload_datetime),
+                    views: row.views,
+                    length: row.length,
+                    thumbnail: row.thumbnail,
+                    youtube_id: row.youtube_id,
+                },
+                score: row.score,
+                captions: vec![],
+                context: vec![],
             });
         }
 
+        hit_line_ids.push(row.line_ids);
+
         let temp_caption = CaptionTextSnippet {
-            url: row.url.unwrap(),
+            url: row.url,
             caption_text: row.caption_text,
+            highlighted: row.highlighted,
             start: row.start,
+            passage: None,
         };
         videos.last_mut().unwrap().captions.push(temp_caption);
     }
 
-    Json(Some(CaptionSearchResults {
-        success: true,
-        videos,
-    }))
+    if options.context > 0 && !hit_line_ids.is_empty() {
+        let all_hit_line_ids: Vec<i32> = hit_line_ids.iter().flatten().copied().collect();
+        let rows = fetch_context_lines(pool, &all_hit_line_ids, options.context).await?;
+        let mut context = group_context_lines(rows, &all_hit_line_ids, options.context);
+
+        let mut hit_line_ids = hit_line_ids.into_iter();
+        for video in &mut videos {
+            let video_context = context.remove(&video.video.id).unwrap_or_default();
+            for caption in &mut video.captions {
+                let line_ids = hit_line_ids.next().unwrap_or_default();
+                caption.passage = line_ids
+                    .first()
+                    .and_then(|id| video_context.passages.get(id).copied());
+            }
+            video.context = video_context.context;
+        }
+    }
+
+    Ok(videos)
+}
+
+#[derive(Debug, Clone, FromRow)]
+struct ContextRow {
+    // The hit this line is near
+    hit_line_id: i32,
+    id: i32,
+    video_id: i32,
+    caption_text: String,
+    start: f64,
+    duration: f64,
+}
+
+// Up to `context` lines either side of each hit, plus one more after as a
+// marker of which line comes next, ordered by hit and then track order.
+// Every hit's window is a couple of index scans, however long its track is.
+async fn fetch_context_lines(
+    pool: &PgPool,
+    hit_line_ids: &[i32],
+    context: u32,
+) -> Result<Vec<ContextRow>, sqlx::Error> {
+    sqlx::query_as::<_, ContextRow>(
+        "
+        select h.id as hit_line_id, l.id, l.video_id, l.caption_text, l.start::float8 as start, l.duration::float8 as duration
+        from caption_timestamps h
+        cross join lateral (
+            (
+                select * from caption_timestamps ct
+                where ct.caption_id = h.caption_id and (ct.start, ct.id) < (h.start, h.id)
+                order by ct.start desc, ct.id desc
+                limit $2
+            )
+            union all
+            (
+                select * from caption_timestamps ct
+                where ct.caption_id = h.caption_id and (ct.start, ct.id) >= (h.start, h.id)
+                order by ct.start, ct.id
+                limit $2 + 2
+            )
+        ) l
+        where h.id = any($1)
+        order by h.id, l.start, l.id",
+    )
+    .bind(hit_line_ids)
+    .bind(context as i64)
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug, Default)]
+struct VideoContext {
+    context: Vec<CaptionContext>,
+    // The passage each line is in, by caption_timestamps id
+    passages: HashMap<i32, usize>,
+}
+
+// Merges the lines around each hit into passages per video, splitting
+// wherever a line isn't followed by the next line in its track
+fn group_context_lines(
+    rows: Vec<ContextRow>,
+    hit_line_ids: &[i32],
+    context: u32,
+) -> HashMap<i32, VideoContext> {
+    let hit_line_ids: HashSet<i32> = hit_line_ids.iter().copied().collect();
+    // The line after each one we know about, and every line to show by video
+    let mut next_line: HashMap<i32, i32> = HashMap::new();
+    let mut lines: HashMap<i32, Vec<ContextRow>> = HashMap::new();
+    let mut seen: HashSet<i32> = HashSet::new();
+
+    let mut rows = rows.into_iter().peekable();
+    while let Some(hit_line_id) = rows.peek().map(|row| row.hit_line_id) {
+        let mut window: Vec<ContextRow> = vec![];
+        while let Some(row) = rows.next_if(|row| row.hit_line_id == hit_line_id) {
+            window.push(row);
+        }
+
+        for pair in window.windows(2) {
+            next_line.insert(pair[0].id, pair[1].id);
+        }
+
+        // Drop the marker line, when there was one
+        let hit = window.iter().position(|row| row.id == hit_line_id);
+        if hit.map(|hit| window.len() - hit - 1) == Some(context as usize + 1) {
+            window.pop();
+        }
+
+        for row in window {
+            if seen.insert(row.id) {
+                lines.entry(row.video_id).or_default().push(row);
+            }
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|(video_id, mut video_lines)| {
+            video_lines.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.id.cmp(&b.id)));
+
+            let mut video_context = VideoContext::default();
+            let mut previous_id: Option<i32> = None;
+            for line in video_lines {
+                if previous_id.and_then(|id| next_line.get(&id)) != Some(&line.id) {
+                    video_context.context.push(CaptionContext {
+                        start: line.start,
+                        end: line.start,
+                        lines: vec![],
+                    });
+                }
+                previous_id = Some(line.id);
+
+                video_context
+                    .passages
+                    .insert(line.id, video_context.context.len() - 1);
+                let passage = video_context.context.last_mut().unwrap();
+                passage.end = line.start + line.duration;
+                passage.lines.push(ContextLine {
+                    caption_text: line.caption_text,
+                    start: line.start,
+                    duration: line.duration,
+                    hit: hit_line_ids.contains(&line.id),
+                });
+            }
+
+            (video_id, video_context)
+        })
+        .collect()
 }
 
 #[get("/video/test")]
@@ -415,3 +694,491 @@ pub async fn test_video(_state: &State<ApiState>) -> Json<SuccessFailResponse> {
 
     Json(SuccessFailResponse { success: true })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::{
+        insert_caption_set, parse_published_at, save_video, NewChannel, VideoToIngest,
+    };
+    use crate::utils::captions::{
+        CaptionKind, FetchedCaptions, YouTubeCaptionTextSnippet, YouTubeCaptionWord,
+    };
+
+    // A video whose captions are `lines`, one every five seconds
+    fn video_to_ingest(
+        youtube_id: &str,
+        published_at: &str,
+        views: i64,
+        lines: &[&str],
+    ) -> VideoToIngest {
+        VideoToIngest {
+            youtube_id: youtube_id.to_string(),
+            channel_youtube_id: "UCuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+            new_channel: Some(NewChannel {
+                title: "Rick Astley".to_string(),
+                thumbnail: String::new(),
+            }),
+            title: youtube_id.to_string(),
+            published_at: parse_published_at(published_at).unwrap(),
+            views,
+            length: 120,
+            thumbnail: String::new(),
+            caption_sets: vec![FetchedCaptions {
+                language: "en".to_string(),
+                kind: CaptionKind::Manual,
+                captions: lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| YouTubeCaptionTextSnippet {
+                        text: line.to_string(),
+                        start: i as f32 * 5.0,
+                        duration: 5.0,
+                        words: None,
+                    })
+                    .collect(),
+            }],
+        }
+    }
+
+    async fn video(
+        pool: &PgPool,
+        youtube_id: &str,
+        published_at: &str,
+        views: i64,
+        lines: &[&str],
+    ) -> i32 {
+        let video = video_to_ingest(youtube_id, published_at, views, lines);
+
+        save_video(pool, &video, None).await.unwrap().video_id
+    }
+
+    fn options(sort: SearchSort) -> SearchOptions {
+        SearchOptions {
+            sort,
+            ..Default::default()
+        }
+    }
+
+    async fn search(pool: &PgPool, text: &str, sort: SearchSort) -> Vec<String> {
+        search_captions(pool, text, &options(sort))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|result| result.video.youtube_id)
+            .collect()
+    }
+
+    async fn videos(pool: &PgPool) {
+        // Mentions tennis once, in passing
+        video(
+            pool,
+            "passing0001",
+            "2023-03-01T00:00:00Z",
+            500,
+            &["we went shopping", "then played tennis", "and had lunch"],
+        )
+        .await;
+        // All about tennis
+        video(
+            pool,
+            "tennis00001",
+            "2023-01-01T00:00:00Z",
+            100,
+            &[
+                "tennis tennis tennis",
+                "a tennis match",
+                "the tennis ball",
+                "more tennis",
+            ],
+        )
+        .await;
+        // Doesn't match at all
+        video(
+            pool,
+            "nomatch0001",
+            "2023-05-01T00:00:00Z",
+            1_000,
+            &["nothing to see here"],
+        )
+        .await;
+    }
+
+    #[sqlx::test]
+    async fn sorts_search_results(pool: PgPool) {
+        videos(&pool).await;
+
+        assert_eq!(
+            search(&pool, "tennis", SearchSort::Relevance).await,
+            vec!["tennis00001", "passing0001"]
+        );
+        assert_eq!(
+            search(&pool, "tennis", SearchSort::Newest).await,
+            vec!["passing0001", "tennis00001"]
+        );
+        assert_eq!(
+            search(&pool, "tennis", SearchSort::Oldest).await,
+            vec!["tennis00001", "passing0001"]
+        );
+        assert_eq!(
+            search(&pool, "tennis", SearchSort::Views).await,
+            vec!["passing0001", "tennis00001"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn returns_scores_and_keeps_captions_in_order(pool: PgPool) {
+        videos(&pool).await;
+
+        let results = search_captions(&pool, "tennis", &options(SearchSort::Relevance))
+            .await
+            .unwrap();
+
+        assert!(results[0].score > results[1].score);
+        assert!(results[1].score > 0.0);
+        let starts: Vec<f64> = results[0].captions.iter().map(|c| c.start).collect();
+        assert_eq!(starts, vec![0.0, 5.0, 10.0, 15.0]);
+    }
+
+    #[test]
+    fn parses_sorts() {
+        assert_eq!(SearchSort::parse("views"), Some(SearchSort::Views));
+        assert_eq!(SearchSort::parse("best"), None);
+    }
+
+    #[sqlx::test]
+    async fn searches_with_phrases_exclusions_and_or(pool: PgPool) {
+        videos(&pool).await;
+
+        assert_eq!(
+            search(&pool, "\"tennis match\"", SearchSort::Newest).await,
+            vec!["tennis00001"]
+        );
+        assert_eq!(
+            search(&pool, "\"match tennis\"", SearchSort::Newest).await,
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            search(&pool, "tennis -played", SearchSort::Newest).await,
+            vec!["tennis00001"]
+        );
+        assert_eq!(
+            search(&pool, "lunch OR ball", SearchSort::Newest).await,
+            vec!["passing0001", "tennis00001"]
+        );
+        // Used to be Postgres syntax errors
+        for text in ["don't stop", "c++", "tennis ", "tennis & match", "(tennis"] {
+            assert!(
+                search_captions(&pool, text, &options(SearchSort::Newest))
+                    .await
+                    .is_ok(),
+                "{text}"
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn searches_imported_transcripts_over_youtube_tracks(pool: PgPool) {
+        let video_id = video(
+            &pool,
+            "imported001",
+            "2023-03-01T00:00:00Z",
+            100,
+            &["youtube's own tennis captions"],
+        )
+        .await;
+        let imported = FetchedCaptions {
+            language: "en".to_string(),
+            kind: CaptionKind::Imported,
+            captions: vec![YouTubeCaptionTextSnippet {
+                text: "our corrected squash transcript".to_string(),
+                start: 0.0,
+                duration: 5.0,
+                words: None,
+            }],
+        };
+        let mut conn = pool.acquire().await.unwrap();
+        insert_caption_set(&mut conn, video_id, &imported)
+            .await
+            .unwrap();
+        // A caption set from before kinds were recorded
+        sqlx::query("alter table captions alter column kind drop not null")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let legacy = FetchedCaptions {
+            language: "en".to_string(),
+            kind: CaptionKind::Asr,
+            captions: vec![YouTubeCaptionTextSnippet {
+                text: "legacy badminton captions".to_string(),
+                start: 0.0,
+                duration: 5.0,
+                words: None,
+            }],
+        };
+        let legacy_id = insert_caption_set(&mut conn, video_id, &legacy)
+            .await
+            .unwrap();
+        sqlx::query("update captions set kind = null where id = $1")
+            .bind(legacy_id)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            search(&pool, "squash", SearchSort::Newest).await,
+            vec!["imported001"]
+        );
+        for text in ["tennis", "badminton"] {
+            assert_eq!(
+                search(&pool, text, SearchSort::Newest).await,
+                Vec::<String>::new()
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn rejects_unsearchable_queries(pool: PgPool) {
+        for (text, expected) in [
+            ("", SearchQueryError::Empty),
+            ("\"tennis", SearchQueryError::UnclosedQuote),
+            ("-tennis", SearchQueryError::OnlyExclusions),
+            ("the -tennis", SearchQueryError::OnlyExclusions),
+            ("the and", SearchQueryError::NoSearchableWords),
+        ] {
+            let result = search_captions(&pool, text, &options(SearchSort::Relevance)).await;
+            assert!(
+                matches!(result, Err(SearchError::Query(ref e)) if *e == expected),
+                "{text}: {result:?}"
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn finds_phrases_split_across_lines(pool: PgPool) {
+        // With word timings a second apart
+        let mut video = video_to_ingest(
+            "machine0001",
+            "2023-01-01T00:00:00Z",
+            100,
+            &[
+                "today we're talking about machine",
+                "learning and how it works",
+                "machine learning is everywhere",
+            ],
+        );
+        for caption in &mut video.caption_sets[0].captions {
+            caption.words = Some(
+                caption
+                    .text
+                    .split(' ')
+                    .enumerate()
+                    .map(|(i, word)| YouTubeCaptionWord {
+                        text: word.to_string(),
+                        offset: i as f32,
+                    })
+                    .collect(),
+            );
+        }
+        save_video(&pool, &video, None).await.unwrap();
+
+        let results = search_captions(&pool, "\"machine learning\"", &options(SearchSort::Newest))
+            .await
+            .unwrap();
+
+        let captions: Vec<(&str, f64)> = results[0]
+            .captions
+            .iter()
+            .map(|c| (c.caption_text.as_str(), c.start))
+            .collect();
+        assert_eq!(
+            captions,
+            vec![
+                // Starting from "machine", at the end of the first line
+                (
+                    "today we're talking about machine learning and how it works",
+                    4.0
+                ),
+                ("machine learning is everywhere", 10.0),
+            ]
+        );
+        assert!(results[0].captions[0].url.ends_with("&t=4s"));
+    }
+
+    #[sqlx::test]
+    async fn links_to_the_first_word_that_matched(pool: PgPool) {
+        // With word timings a second apart
+        let mut video = video_to_ingest(
+            "wordlink001",
+            "2023-01-01T00:00:00Z",
+            100,
+            &["match point then a tennis match", "or lunch after squash"],
+        );
+        for caption in &mut video.caption_sets[0].captions {
+            caption.words = Some(
+                caption
+                    .text
+                    .split(' ')
+                    .enumerate()
+                    .map(|(i, word)| YouTubeCaptionWord {
+                        text: word.to_string(),
+                        offset: i as f32,
+                    })
+                    .collect(),
+            );
+        }
+        save_video(&pool, &video, None).await.unwrap();
+        let first_hit = |text: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let results = search_captions(&pool, text, &options(SearchSort::Newest))
+                    .await
+                    .unwrap();
+                let hit = &results[0].captions[0];
+                (hit.start, hit.url.clone())
+            }
+        };
+
+        // Not the "match" at the start of the line, which isn't part of the
+        // phrase
+        let (start, url) = first_hit("\"tennis match\"").await;
+        assert_eq!(start, 4.0);
+        assert!(url.ends_with("&t=4s"));
+
+        assert_eq!(first_hit("tennis point").await.0, 1.0);
+        // "or" is a stop word, not one of the terms
+        assert_eq!(first_hit("squash OR lunch").await.0, 6.0);
+        assert_eq!(first_hit("tennis -squash").await.0, 4.0);
+    }
+
+    #[sqlx::test]
+    async fn highlights_matched_words(pool: PgPool) {
+        videos(&pool).await;
+
+        let results = search_captions(&pool, "matches", &options(SearchSort::Newest))
+            .await
+            .unwrap();
+        assert_eq!(results[0].captions[0].highlighted, "a tennis <b>match</b>");
+
+        // Markers can have quotes and commas in them
+        let options = SearchOptions {
+            highlight_start: "<mark class=\"hit\">".to_string(),
+            highlight_stop: "</mark>,".to_string(),
+            ..options(SearchSort::Newest)
+        };
+        let results = search_captions(&pool, "played tennis", &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            results[0].captions[0].highlighted,
+            "then <mark class=\"hit\">played</mark>, <mark class=\"hit\">tennis</mark>,"
+        );
+    }
+
+    #[sqlx::test]
+    async fn escapes_html_in_highlighted_text(pool: PgPool) {
+        video(
+            &pool,
+            "markup00001",
+            "2023-03-01T00:00:00Z",
+            100,
+            &["<img src=x onerror=alert(1)> \"tennis\" & Tom's <b>squash</b>"],
+        )
+        .await;
+
+        let results = search_captions(&pool, "tennis squash", &options(SearchSort::Newest))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            results[0].captions[0].highlighted,
+            "&lt;img src=x onerror=alert(1)&gt; &quot;<b>tennis</b>&quot; &amp; Tom&#39;s &lt;b&gt;<b>squash</b>&lt;/b&gt;"
+        );
+        assert_eq!(
+            results[0].captions[0].caption_text,
+            "<img src=x onerror=alert(1)> \"tennis\" & Tom's <b>squash</b>"
+        );
+    }
+
+    #[sqlx::test]
+    async fn returns_merged_context_around_hits(pool: PgPool) {
+        video(
+            &pool,
+            "context0001",
+            "2023-01-01T00:00:00Z",
+            100,
+            &[
+                "zero",
+                "one kiwi",
+                "two",
+                "three kiwi",
+                "four",
+                "five",
+                "six",
+                "seven",
+                "eight kiwi",
+                "nine",
+            ],
+        )
+        .await;
+
+        let with_context = SearchOptions {
+            context: 1,
+            ..options(SearchSort::Newest)
+        };
+        let results = search_captions(&pool, "kiwi", &with_context).await.unwrap();
+
+        // The context around "one" and "three" overlaps, so they share a passage
+        let passages: Vec<Vec<(&str, bool)>> = results[0]
+            .context
+            .iter()
+            .map(|passage| {
+                passage
+                    .lines
+                    .iter()
+                    .map(|line| (line.caption_text.as_str(), line.hit))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            passages,
+            vec![
+                vec![
+                    ("zero", false),
+                    ("one kiwi", true),
+                    ("two", false),
+                    ("three kiwi", true),
+                    ("four", false),
+                ],
+                vec![("seven", false), ("eight kiwi", true), ("nine", false)],
+            ]
+        );
+        assert_eq!(
+            (results[0].context[1].start, results[0].context[1].end),
+            (35.0, 50.0)
+        );
+        let hit_passages: Vec<Option<usize>> =
+            results[0].captions.iter().map(|c| c.passage).collect();
+        assert_eq!(hit_passages, vec![Some(0), Some(0), Some(1)]);
+
+        // Two lines either side of "three" runs up to "five", and of "eight"
+        // back to "six", so the passages meet without overlapping
+        let with_context = SearchOptions {
+            context: 2,
+            ..options(SearchSort::Newest)
+        };
+        let results = search_captions(&pool, "kiwi", &with_context).await.unwrap();
+        assert_eq!(results[0].context.len(), 1);
+        assert_eq!(results[0].context[0].lines.len(), 10);
+        let hit_passages: Vec<Option<usize>> =
+            results[0].captions.iter().map(|c| c.passage).collect();
+        assert_eq!(hit_passages, vec![Some(0), Some(0), Some(0)]);
+
+        // No context unless asked for
+        let results = search_captions(&pool, "kiwi", &options(SearchSort::Newest))
+            .await
+            .unwrap();
+        assert!(results[0].context.is_empty());
+        assert_eq!(results[0].captions[0].passage, None);
+    }
+}
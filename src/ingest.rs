@@ -0,0 +1,752 @@
+use crate::utils::caption_source::CaptionSource;
+use crate::utils::captions::{
+    fetch_captions, text_search_config, CaptionError, CaptionPreference, FetchedCaptions,
+    YouTubeCaptionTextSnippet,
+};
+use crate::utils::duration::parse_iso8601_duration;
+use crate::utils::youtube_url::ChannelRef;
+use crate::youtube::{ChannelDetails, YouTubeApi, YouTubeApiError};
+use chrono::{DateTime, Utc};
+use rocket::serde::{Deserialize, Serialize};
+use sqlx::{Error, PgConnection, PgPool};
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct IngestOptions {
+    pub caption_preferences: Vec<CaptionPreference>,
+    // Re-fetch the details and captions of a video we've already ingested
+    pub refresh: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IngestOutcome {
+    pub video_id: i32,
+    pub already_existed: bool,
+    pub refreshed: bool,
+}
+
+#[derive(Debug)]
+pub enum IngestError {
+    YouTube(YouTubeApiError),
+    // The Data API gave us something we couldn't make sense of
+    InvalidVideoDetails(String),
+    Captions(CaptionError),
+    Database(Error),
+    // Something went badly wrong; only produced by the job workers, which run
+    // each ingestion on its own task
+    Panicked(String),
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::YouTube(e) => write!(f, "{e}"),
+            IngestError::InvalidVideoDetails(e) => write!(f, "Invalid video details: {e}"),
+            IngestError::Captions(e) => write!(f, "{e}"),
+            IngestError::Database(e) => write!(f, "Database error: {e}"),
+            IngestError::Panicked(e) => write!(f, "Ingestion crashed: {e}"),
+        }
+    }
+}
+
+impl IngestError {
+    // A short machine readable name for the error, in the style of
+    // `ErrorResponse.error`
+    pub fn code(&self) -> &'static str {
+        match self {
+            IngestError::YouTube(e) => e.code(),
+            IngestError::InvalidVideoDetails(_) => "invalid_video_details",
+            IngestError::Database(_) => "database_error",
+            IngestError::Panicked(_) => "ingestion_crashed",
+            IngestError::Captions(e) => match e {
+                CaptionError::TooManyRequests(_) => "too_many_requests",
+                CaptionError::VideoUnavailable(_) => "video_unavailable",
+                CaptionError::TranscriptsDisabled(_) => "transcripts_disabled",
+                CaptionError::NoTranscriptAvailable(_) => "no_transcript_available",
+                CaptionError::NoMatchingTranscript(_) => "no_matching_transcript",
+                CaptionError::MalformedJson(_) => "malformed_caption_data",
+                CaptionError::MalformedXml(_) => "malformed_caption_data",
+                CaptionError::Request(_) => "caption_request_failed",
+                CaptionError::Io(_) => "caption_read_failed",
+            },
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+impl From<YouTubeApiError> for IngestError {
+    fn from(e: YouTubeApiError) -> Self {
+        IngestError::YouTube(e)
+    }
+}
+
+impl From<CaptionError> for IngestError {
+    fn from(e: CaptionError) -> Self {
+        IngestError::Captions(e)
+    }
+}
+
+impl From<Error> for IngestError {
+    fn from(e: Error) -> Self {
+        IngestError::Database(e)
+    }
+}
+
+// A channel we haven't seen before, to be inserted along with its first video
+#[derive(Debug, Clone)]
+pub struct NewChannel {
+    pub title: String,
+    pub thumbnail: String,
+}
+
+// Everything we pulled from YouTube for a video, ready to be written to the
+// database in one go
+#[derive(Debug, Clone)]
+pub struct VideoToIngest {
+    pub youtube_id: String,
+    pub channel_youtube_id: String,
+    pub new_channel: Option<NewChannel>,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    pub views: i64,
+    pub length: i32,
+    pub thumbnail: String,
+    pub caption_sets: Vec<FetchedCaptions>,
+}
+
+// `publishedAt` is RFC 3339, e.g. "2023-04-12T15:00:09Z"
+pub fn parse_published_at(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .ok()
+        .map(|d| d.with_timezone(&Utc))
+}
+
+// Looks up when each video was published, in batches of 50 (the most the Data
+// API takes per request). Videos the API no longer knows about are left out.
+pub async fn fetch_published_dates(
+    youtube: &dyn YouTubeApi,
+    youtube_ids: &[String],
+) -> Result<Vec<(String, DateTime<Utc>)>, YouTubeApiError> {
+    let mut published_dates: Vec<(String, DateTime<Utc>)> = vec![];
+
+    for ids in youtube_ids.chunks(50) {
+        for video in youtube.videos(ids).await? {
+            if let Some(published_at) = parse_published_at(&video.published_at) {
+                published_dates.push((video.youtube_id, published_at));
+            }
+        }
+    }
+
+    Ok(published_dates)
+}
+
+// Ingests a video (its channel, details and every caption track). All the
+// fetching happens up front and the writes happen in a single transaction, so
+// a failure at any point leaves nothing behind.
+pub async fn ingest_video(
+    pool: &PgPool,
+    youtube: &dyn YouTubeApi,
+    caption_source: &dyn CaptionSource,
+    youtube_id: &str,
+    options: &IngestOptions,
+) -> Result<IngestOutcome, IngestError> {
+    // Submitting a video we already have just hands back its id, unless the
+    // caller asked for it to be refreshed
+    let existing_video_id: Option<i32> =
+        sqlx::query_scalar("select id from videos where youtube_id=$1")
+            .bind(youtube_id)
+            .fetch_optional(pool)
+            .await?;
+
+    if let (Some(video_id), false) = (existing_video_id, options.refresh) {
+        return Ok(IngestOutcome {
+            video_id,
+            already_existed: true,
+            refreshed: false,
+        });
+    }
+
+    let video = fetch_video(pool, youtube, caption_source, youtube_id, options).await?;
+
+    Ok(save_video(pool, &video, existing_video_id).await?)
+}
+
+async fn fetch_video(
+    pool: &PgPool,
+    youtube: &dyn YouTubeApi,
+    caption_source: &dyn CaptionSource,
+    youtube_id: &str,
+    options: &IngestOptions,
+) -> Result<VideoToIngest, IngestError> {
+    let details = youtube.video(youtube_id).await?;
+
+    let length = parse_iso8601_duration(&details.duration)
+        .map_err(|e| IngestError::InvalidVideoDetails(e.to_string()))?;
+    let published_at = parse_published_at(&details.published_at).ok_or_else(|| {
+        IngestError::InvalidVideoDetails(format!(
+            "Could not parse publish date \"{}\"",
+            details.published_at
+        ))
+    })?;
+    let view_count = details.view_count.unwrap_or_default();
+    let views = view_count.parse::<i64>().map_err(|_| {
+        IngestError::InvalidVideoDetails(format!("Could not parse view count \"{view_count}\""))
+    })?;
+
+    let caption_sets = fetch_captions(
+        caption_source,
+        youtube_id.to_string(),
+        &options.caption_preferences,
+    )
+    .await?;
+
+    // Only look the channel up if we don't have it yet
+    let channel_youtube_id = details.channel_youtube_id;
+    let channel_id: Option<i32> = sqlx::query_scalar("select id from channels where youtube_id=$1")
+        .bind(&channel_youtube_id)
+        .fetch_optional(pool)
+        .await?;
+
+    let new_channel = match channel_id {
+        Some(_) => None,
+        None => {
+            let ChannelDetails {
+                title, thumbnail, ..
+            } = youtube
+                .channel(&ChannelRef::Id(channel_youtube_id.clone()))
+                .await?;
+
+            Some(NewChannel { title, thumbnail })
+        }
+    };
+
+    Ok(VideoToIngest {
+        youtube_id: youtube_id.to_string(),
+        channel_youtube_id,
+        new_channel,
+        title: details.title,
+        published_at,
+        views,
+        length,
+        thumbnail: details.thumbnail,
+        caption_sets,
+    })
+}
+
+// Writes a fetched video in a single transaction. Dropping the transaction on
+// an error rolls back everything written so far.
+pub async fn save_video(
+    pool: &PgPool,
+    video: &VideoToIngest,
+    existing_video_id: Option<i32>,
+) -> Result<IngestOutcome, Error> {
+    let mut tx = pool.begin().await?;
+    let outcome = store_video(&mut tx, video, existing_video_id).await?;
+    tx.commit().await?;
+
+    Ok(outcome)
+}
+
+async fn store_video(
+    conn: &mut PgConnection,
+    video: &VideoToIngest,
+    existing_video_id: Option<i32>,
+) -> Result<IngestOutcome, Error> {
+    let channel_id: i32 = match &video.new_channel {
+        // Another request may have added the channel since we looked
+        Some(channel) => sqlx::query_scalar("insert into channels (title, url, thumbnail, youtube_id) values ($1, $2, $3, $4) on conflict (youtube_id) where youtube_id != '' do update set title=excluded.title returning id")
+            .bind(&channel.title)
+            .bind(format!("https://youtube.com/channel/{}", video.channel_youtube_id))
+            .bind(&channel.thumbnail)
+            .bind(&video.channel_youtube_id)
+            .fetch_one(&mut *conn)
+            .await?,
+        None => sqlx::query_scalar("select id from channels where youtube_id=$1")
+            .bind(&video.channel_youtube_id)
+            .fetch_one(&mut *conn)
+            .await?,
+    };
+
+    let video_id = match existing_video_id {
+        Some(video_id) => {
+            sqlx::query("update videos set channel_id=$1, title=$2, upload_datetime=$3, views=$4, length=$5, thumbnail=$6 where id=$7")
+                .bind(channel_id)
+                .bind(&video.title)
+                .bind(video.published_at)
+                .bind(video.views)
+                .bind(video.length)
+                .bind(&video.thumbnail)
+                .bind(video_id)
+                .execute(&mut *conn)
+                .await?;
+
+            // Swap out the captions we pulled from YouTube, but leave any
+            // transcripts users imported alone
+            let youtube_caption_ids: Vec<i32> = sqlx::query_scalar(
+                "select id from captions where video_id=$1 and kind is distinct from 'imported'",
+            )
+            .bind(video_id)
+            .fetch_all(&mut *conn)
+            .await?;
+            delete_caption_sets(&mut *conn, &youtube_caption_ids).await?;
+
+            video_id
+        }
+        None => {
+            let result: Option<i32> =
+                sqlx::query_scalar("insert into videos (channel_id, title, url, upload_datetime, views, length, thumbnail, youtube_id, ingested_at) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) on conflict (youtube_id) where youtube_id != '' do nothing returning id")
+                    .bind(channel_id)
+                    .bind(&video.title)
+                    .bind(format!("https://www.youtube.com/watch?v={}", video.youtube_id))
+                    .bind(video.published_at)
+                    .bind(video.views)
+                    .bind(video.length)
+                    .bind(&video.thumbnail)
+                    .bind(&video.youtube_id)
+                    .bind(Utc::now())
+                    .fetch_optional(&mut *conn)
+                    .await?;
+
+            match result {
+                Some(video_id) => video_id,
+                None => {
+                    // Someone else ingested the same video while we were
+                    // fetching it
+                    let video_id: i32 =
+                        sqlx::query_scalar("select id from videos where youtube_id=$1")
+                            .bind(&video.youtube_id)
+                            .fetch_one(&mut *conn)
+                            .await?;
+
+                    return Ok(IngestOutcome {
+                        video_id,
+                        already_existed: true,
+                        refreshed: false,
+                    });
+                }
+            }
+        }
+    };
+
+    for caption_set in &video.caption_sets {
+        insert_caption_set(&mut *conn, video_id, caption_set).await?;
+    }
+
+    Ok(IngestOutcome {
+        video_id,
+        already_existed: existing_video_id.is_some(),
+        refreshed: existing_video_id.is_some(),
+    })
+}
+
+// Writes one caption track into `captions`, plus a `caption_timestamps` row per
+// caption line and a `caption_windows` row per pair of adjacent lines,
+// returning the new caption id
+pub async fn insert_caption_set(
+    conn: &mut PgConnection,
+    video_id: i32,
+    caption_set: &FetchedCaptions,
+) -> Result<i32, Error> {
+    // In the order they're said, which imported transcripts and merged cues
+    // aren't always in. Adjacent lines are paired up in this order, the same
+    // as the caption_windows migration pairs them by `start, id`.
+    let mut video_captions: Vec<&YouTubeCaptionTextSnippet> = caption_set.captions.iter().collect();
+    video_captions.sort_by(|a, b| a.start.total_cmp(&b.start));
+    let raw_text = video_captions
+        .iter()
+        .fold(String::new(), |acc, s| acc + &s.text + " ");
+    let caption_id: i32 = sqlx::query_scalar(
+        "insert into captions (video_id, raw_text, caption_json, language, kind) values ($1, $2, $3, $4, $5) returning id",
+    )
+    .bind(video_id)
+    .bind(raw_text)
+    .bind(sqlx::types::Json(&video_captions))
+    .bind(&caption_set.language)
+    .bind(caption_set.kind.as_str())
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let video_ids = video_captions
+        .iter()
+        .map(|_c| video_id)
+        .collect::<Vec<i32>>();
+    let caption_ids = video_captions
+        .iter()
+        .map(|_c| caption_id)
+        .collect::<Vec<i32>>();
+    let caption_texts = video_captions
+        .iter()
+        .map(|c| c.text.clone())
+        .collect::<Vec<String>>();
+    let caption_starts = video_captions.iter().map(|c| c.start).collect::<Vec<f32>>();
+    let caption_durations = video_captions
+        .iter()
+        .map(|c| c.duration)
+        .collect::<Vec<f32>>();
+
+    let caption_timestamp_ids: Vec<i32> = sqlx::query_scalar(
+        "insert into caption_timestamps (video_id, caption_id, caption_text, start, duration, ts_config) select *, $6::text::regconfig from unnest($1, $2, $3, $4, $5) returning id",
+    )
+    .bind(video_ids)
+    .bind(caption_ids)
+    .bind(caption_texts)
+    .bind(caption_starts)
+    .bind(caption_durations)
+    .bind(text_search_config(&caption_set.language))
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // Each line paired with the next, for phrases that run from one line into
+    // the next
+    if caption_timestamp_ids.len() > 1 {
+        let window_texts = video_captions
+            .windows(2)
+            .map(|pair| format!("{} {}", pair[0].text, pair[1].text))
+            .collect::<Vec<String>>();
+
+        sqlx::query(
+            "insert into caption_windows (video_id, caption_id, caption_timestamp_id, next_caption_timestamp_id, window_text, ts_config) select $1, $2, *, $6::text::regconfig from unnest($3, $4, $5)",
+        )
+        .bind(video_id)
+        .bind(caption_id)
+        .bind(&caption_timestamp_ids[..caption_timestamp_ids.len() - 1])
+        .bind(&caption_timestamp_ids[1..])
+        .bind(window_texts)
+        .bind(text_search_config(&caption_set.language))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    // Word timings are stored with their absolute start, so search can link
+    // straight to the word that matched
+    let mut word_timestamp_ids: Vec<i32> = vec![];
+    let mut word_texts: Vec<String> = vec![];
+    let mut word_starts: Vec<f32> = vec![];
+
+    for (caption, caption_timestamp_id) in video_captions.iter().zip(caption_timestamp_ids) {
+        for word in caption.words.iter().flatten() {
+            word_timestamp_ids.push(caption_timestamp_id);
+            word_texts.push(word.text.clone());
+            word_starts.push(caption.start + word.offset);
+        }
+    }
+
+    if !word_texts.is_empty() {
+        sqlx::query(
+            "insert into caption_words (video_id, caption_id, caption_timestamp_id, word, start) select $1, $2, * from unnest($3, $4, $5)",
+        )
+        .bind(video_id)
+        .bind(caption_id)
+        .bind(word_timestamp_ids)
+        .bind(word_texts)
+        .bind(word_starts)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(caption_id)
+}
+
+// Removes caption sets along with their timestamps and word timings
+pub async fn delete_caption_sets(
+    conn: &mut PgConnection,
+    caption_ids: &[i32],
+) -> Result<(), Error> {
+    for table in ["caption_words", "caption_windows", "caption_timestamps"] {
+        sqlx::query(&format!("delete from {table} where caption_id = any($1)"))
+            .bind(caption_ids)
+            .execute(&mut *conn)
+            .await?;
+    }
+    sqlx::query("delete from captions where id = any($1)")
+        .bind(caption_ids)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::caption_source::FileCaptionSource;
+    use crate::utils::captions::{
+        default_caption_preferences, CaptionKind, YouTubeCaptionTextSnippet, YouTubeCaptionWord,
+    };
+    use crate::utils::http::HttpClient;
+    use crate::utils::test_server;
+    use crate::youtube::DataApiClient;
+    use chrono::TimeZone;
+
+    const TABLES: [&str; 6] = [
+        "channels",
+        "videos",
+        "captions",
+        "caption_timestamps",
+        "caption_words",
+        "caption_windows",
+    ];
+
+    fn video_to_ingest(new_channel: bool) -> VideoToIngest {
+        let caption = |text: &str, start: f32| YouTubeCaptionTextSnippet {
+            text: text.to_string(),
+            start,
+            duration: 2.0,
+            words: Some(vec![YouTubeCaptionWord {
+                text: text.to_string(),
+                offset: 0.5,
+            }]),
+        };
+
+        VideoToIngest {
+            youtube_id: "dQw4w9WgXcQ".to_string(),
+            channel_youtube_id: "UCuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+            new_channel: new_channel.then(|| NewChannel {
+                title: "Rick Astley".to_string(),
+                thumbnail: "https://yt3.ggpht.com/rick".to_string(),
+            }),
+            title: "Never Gonna Give You Up".to_string(),
+            published_at: parse_published_at("2009-10-25T06:57:33Z").unwrap(),
+            views: 1_000,
+            length: 213,
+            thumbnail: "https://i.ytimg.com/vi/dQw4w9WgXcQ/default.jpg".to_string(),
+            caption_sets: vec![
+                FetchedCaptions {
+                    language: "en".to_string(),
+                    kind: CaptionKind::Manual,
+                    captions: vec![caption("never", 1.0), caption("gonna", 3.0)],
+                },
+                FetchedCaptions {
+                    language: "en".to_string(),
+                    kind: CaptionKind::Asr,
+                    captions: vec![caption("give", 5.0)],
+                },
+            ],
+        }
+    }
+
+    async fn row_counts(pool: &PgPool) -> Vec<i64> {
+        let mut counts = vec![];
+        for table in TABLES {
+            let count: i64 = sqlx::query_scalar(&format!("select count(*) from {table}"))
+                .fetch_one(pool)
+                .await
+                .unwrap();
+            counts.push(count);
+        }
+
+        counts
+    }
+
+    // Makes every insert into `table` blow up, standing in for whatever might
+    // go wrong partway through an ingestion
+    async fn fail_inserts_into(pool: &PgPool, table: &str) {
+        sqlx::query(
+            "create function fail_insert() returns trigger as $$ begin raise exception 'simulated failure'; end $$ language plpgsql",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(&format!(
+            "create trigger fail_insert before insert on {table} for each row execute function fail_insert()"
+        ))
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn parses_published_at() {
+        let expected = Utc.with_ymd_and_hms(2023, 4, 12, 15, 0, 9).unwrap();
+
+        assert_eq!(parse_published_at("2023-04-12T15:00:09Z"), Some(expected));
+        assert_eq!(
+            parse_published_at("2023-04-12T08:00:09-07:00"),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_published_at("2023-04-12T15:00:09.250Z"),
+            Some(expected + chrono::Duration::milliseconds(250))
+        );
+        assert_eq!(parse_published_at("2023-04-12"), None);
+        assert_eq!(parse_published_at(""), None);
+    }
+
+    #[sqlx::test]
+    async fn saves_everything(pool: PgPool) {
+        let outcome = save_video(&pool, &video_to_ingest(true), None)
+            .await
+            .unwrap();
+
+        assert!(!outcome.already_existed);
+        assert_eq!(row_counts(&pool).await, vec![1, 1, 2, 3, 3, 1]);
+    }
+
+    #[sqlx::test]
+    async fn failure_at_any_step_leaves_no_rows(pool: PgPool) {
+        for table in TABLES {
+            sqlx::query("drop function if exists fail_insert() cascade")
+                .execute(&pool)
+                .await
+                .unwrap();
+            fail_inserts_into(&pool, table).await;
+
+            let result = save_video(&pool, &video_to_ingest(true), None).await;
+
+            assert!(result.is_err(), "insert into {table} should have failed");
+            assert_eq!(
+                row_counts(&pool).await,
+                vec![0, 0, 0, 0, 0, 0],
+                "failing on {table}"
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn failed_refresh_keeps_old_captions(pool: PgPool) {
+        let outcome = save_video(&pool, &video_to_ingest(true), None)
+            .await
+            .unwrap();
+
+        fail_inserts_into(&pool, "caption_words").await;
+        let result = save_video(&pool, &video_to_ingest(false), Some(outcome.video_id)).await;
+
+        assert!(result.is_err());
+        assert_eq!(row_counts(&pool).await, vec![1, 1, 2, 3, 3, 1]);
+    }
+
+    #[sqlx::test]
+    async fn refresh_replaces_captions(pool: PgPool) {
+        let outcome = save_video(&pool, &video_to_ingest(true), None)
+            .await
+            .unwrap();
+
+        let mut video = video_to_ingest(false);
+        video.caption_sets.truncate(1);
+        let refreshed = save_video(&pool, &video, Some(outcome.video_id))
+            .await
+            .unwrap();
+
+        assert_eq!(refreshed.video_id, outcome.video_id);
+        assert!(refreshed.refreshed);
+        assert_eq!(row_counts(&pool).await, vec![1, 1, 1, 2, 2, 1]);
+    }
+
+    #[sqlx::test]
+    async fn pairs_lines_in_the_order_they_are_said(pool: PgPool) {
+        let outcome = save_video(&pool, &video_to_ingest(true), None)
+            .await
+            .unwrap();
+        let line = |text: &str, start: f32| YouTubeCaptionTextSnippet {
+            text: text.to_string(),
+            start,
+            duration: 1.0,
+            words: None,
+        };
+        let caption_set = FetchedCaptions {
+            language: "en".to_string(),
+            kind: CaptionKind::Imported,
+            captions: vec![line("three", 3.0), line("one", 1.0), line("two", 2.0)],
+        };
+
+        let mut conn = pool.acquire().await.unwrap();
+        let caption_id = insert_caption_set(&mut conn, outcome.video_id, &caption_set)
+            .await
+            .unwrap();
+
+        let windows: Vec<String> = sqlx::query_scalar(
+            "select window_text from caption_windows where caption_id=$1 order by id",
+        )
+        .bind(caption_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(windows, vec!["one two", "two three"]);
+    }
+
+    // Stands in for the Data API, knowing about a single video and its channel
+    async fn mock_youtube() -> (DataApiClient, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        let (base_url, requests) = test_server::serve(|target| {
+            let body = if target.starts_with("/videos?") && target.ends_with("id=fixture0001") {
+                r#"{"items": [{
+                    "id": "fixture0001",
+                    "snippet": {"publishedAt": "2023-04-12T15:00:09Z", "channelId": "UCuAXFkgsw1L7xaCfnd5JJOw", "title": "Fixture video", "thumbnails": {"default": {"url": "https://i.ytimg.com/vi/fixture0001/default.jpg"}}},
+                    "contentDetails": {"duration": "PT1M5S"},
+                    "statistics": {"viewCount": "42"}
+                }]}"#
+            } else if target.starts_with("/channels?") {
+                r#"{"items": [{"id": "UCuAXFkgsw1L7xaCfnd5JJOw", "snippet": {"title": "Rick Astley", "thumbnails": {"default": {"url": "https://yt3.ggpht.com/rick"}}}}]}"#
+            } else {
+                r#"{"items": []}"#
+            };
+
+            test_server::response("200 OK", &[("Content-Type", "application/json")], body)
+        })
+        .await;
+
+        let youtube =
+            DataApiClient::new(HttpClient::new().unwrap(), "test-key".to_string(), base_url);
+
+        (youtube, requests)
+    }
+
+    fn ingest_options() -> IngestOptions {
+        IngestOptions {
+            caption_preferences: default_caption_preferences(),
+            refresh: false,
+        }
+    }
+
+    #[sqlx::test]
+    async fn ingests_from_the_data_api(pool: PgPool) {
+        let (youtube, requests) = mock_youtube().await;
+        let caption_source = FileCaptionSource::new("tests/fixtures/captions");
+
+        let outcome = ingest_video(
+            &pool,
+            &youtube,
+            &caption_source,
+            "fixture0001",
+            &ingest_options(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!outcome.already_existed);
+        let (title, views, length, channel): (String, i64, i32, String) = sqlx::query_as(
+            "select v.title, v.views, v.length, ch.title from videos v join channels ch on ch.id=v.channel_id where v.id=$1",
+        )
+        .bind(outcome.video_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((title.as_str(), views, length), ("Fixture video", 42, 65));
+        assert_eq!(channel, "Rick Astley");
+        assert!(row_counts(&pool).await[2] > 0);
+
+        // The channel is only looked up because we didn't have it yet
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("/channels?key=test-key&"));
+    }
+
+    #[sqlx::test]
+    async fn unknown_videos_are_not_ingested(pool: PgPool) {
+        let (youtube, _) = mock_youtube().await;
+        let caption_source = FileCaptionSource::new("tests/fixtures/captions");
+
+        let err = ingest_video(
+            &pool,
+            &youtube,
+            &caption_source,
+            "fixture0002",
+            &ingest_options(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.code(), "video_not_found");
+        assert_eq!(row_counts(&pool).await, vec![0, 0, 0, 0, 0, 0]);
+    }
+}
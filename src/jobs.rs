@@ -0,0 +1,538 @@
+use crate::ingest::{ingest_video, IngestError, IngestOptions, IngestOutcome};
+use crate::quota::{quota_resets_at, Quota};
+use crate::utils::caption_source::CaptionSource;
+use crate::utils::captions::CaptionPreference;
+use crate::youtube::{YouTubeApi, YouTubeApiError};
+use chrono::{DateTime, Utc};
+use rocket::serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, PgPool};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// How often idle workers look for jobs, in case they missed a wake up (e.g. a
+// job queued by another process)
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// A job still running after this long is taken to have been abandoned by a
+// worker that died, and is handed to another one. Well over the time an
+// ingestion takes even with every request timing out and being retried.
+const JOB_LEASE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Job {
+    pub id: i32,
+    pub youtube_id: String,
+    pub refresh: bool,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub error_message: Option<String>,
+    pub video_id: Option<i32>,
+    pub already_existed: Option<bool>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// One video from a batch (a playlist or a channel's uploads) and whether it
+// was queued
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedVideo {
+    pub youtube_id: String,
+    pub already_indexed: bool,
+    // Whether a job for the video was already waiting or in progress
+    pub already_queued: bool,
+    // Poll `GET /job/<id>` to find out how ingestion went. `None` when the
+    // video was skipped.
+    pub job_id: Option<i32>,
+}
+
+const JOB_COLUMNS: &str = "id, youtube_id, refresh, status, error, error_message, video_id, already_existed, created_at, started_at, finished_at";
+
+// Video ingestion jobs, stored in `ingestion_jobs` and worked through by a pool
+// of tokio tasks
+#[derive(Clone)]
+pub struct JobQueue {
+    pool: PgPool,
+    wake: Arc<Notify>,
+    quota: Option<Quota>,
+    // Set when YouTube itself says we're out of quota, which our own count
+    // can't know about (e.g. the budget is set too high)
+    paused_until: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+impl JobQueue {
+    pub fn new(pool: PgPool) -> JobQueue {
+        JobQueue {
+            pool,
+            wake: Arc::new(Notify::new()),
+            quota: None,
+            paused_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Holds jobs in the queue while `quota`'s daily budget is used up
+    pub fn with_quota(mut self, quota: Quota) -> JobQueue {
+        self.quota = Some(quota);
+        self
+    }
+
+    pub async fn enqueue_video(
+        &self,
+        youtube_id: &str,
+        options: &IngestOptions,
+    ) -> Result<i32, Error> {
+        let job_id: i32 = sqlx::query_scalar(
+            "insert into ingestion_jobs (youtube_id, caption_preferences, refresh) values ($1, $2, $3) returning id",
+        )
+        .bind(youtube_id)
+        .bind(sqlx::types::Json(&options.caption_preferences))
+        .bind(options.refresh)
+        .fetch_one(&self.pool)
+        .await?;
+
+        self.wake.notify_one();
+
+        Ok(job_id)
+    }
+
+    // Queues the videos we haven't indexed yet, or all of them when refreshing.
+    // Videos with a job already queued or running are skipped either way, so
+    // submitting the same playlist twice doesn't spend quota on them twice.
+    pub async fn enqueue_videos(
+        &self,
+        youtube_ids: Vec<String>,
+        options: &IngestOptions,
+    ) -> Result<Vec<QueuedVideo>, Error> {
+        let indexed: HashSet<String> =
+            sqlx::query_scalar("select youtube_id from videos where youtube_id = any($1)")
+                .bind(&youtube_ids)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .collect();
+        let pending: HashSet<String> = sqlx::query_scalar(
+            "select youtube_id from ingestion_jobs where youtube_id = any($1) and status in ('queued', 'running')",
+        )
+        .bind(&youtube_ids)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        let mut videos: Vec<QueuedVideo> = vec![];
+        for youtube_id in youtube_ids {
+            let already_indexed = indexed.contains(&youtube_id);
+            let already_queued = pending.contains(&youtube_id);
+            let job_id = if already_queued || (already_indexed && !options.refresh) {
+                None
+            } else {
+                Some(self.enqueue_video(&youtube_id, options).await?)
+            };
+
+            videos.push(QueuedVideo {
+                youtube_id,
+                already_indexed,
+                already_queued,
+                job_id,
+            });
+        }
+
+        Ok(videos)
+    }
+
+    pub async fn get(&self, job_id: i32) -> Result<Option<Job>, Error> {
+        sqlx::query_as::<_, Job>(&format!(
+            "select {JOB_COLUMNS} from ingestion_jobs where id=$1"
+        ))
+        .bind(job_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // Starts `count` workers. Other processes may be running workers on the
+    // same queue, so jobs they're running are left alone; ones abandoned by a
+    // process that died are picked up again once their lease runs out.
+    pub fn start_workers(
+        &self,
+        youtube: Arc<dyn YouTubeApi>,
+        caption_source: Arc<dyn CaptionSource>,
+        count: usize,
+    ) {
+        for _ in 0..count {
+            let queue = self.clone();
+            let youtube = youtube.clone();
+            let caption_source = caption_source.clone();
+            tokio::spawn(async move { queue.work(youtube, caption_source).await });
+        }
+    }
+
+    async fn work(&self, youtube: Arc<dyn YouTubeApi>, caption_source: Arc<dyn CaptionSource>) {
+        loop {
+            if let Some(resumes_at) = self.deferred_until().await {
+                let wait = (resumes_at - Utc::now()).to_std().unwrap_or(POLL_INTERVAL);
+                tokio::time::sleep(wait).await;
+                continue;
+            }
+
+            match claim_next_job(&self.pool).await {
+                Ok(Some(job)) => self.run(job, youtube.clone(), caption_source.clone()).await,
+                Ok(None) => {
+                    tokio::select! {
+                        _ = self.wake.notified() => {}
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Could not claim an ingestion job: {e}");
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    async fn run(
+        &self,
+        job: ClaimedJob,
+        youtube: Arc<dyn YouTubeApi>,
+        caption_source: Arc<dyn CaptionSource>,
+    ) {
+        let pool = self.pool.clone();
+        let options = IngestOptions {
+            caption_preferences: job.caption_preferences.0,
+            refresh: job.refresh,
+        };
+
+        // Ingest on its own task, so a panic fails the job instead of taking
+        // the worker down with it
+        let result = tokio::spawn(async move {
+            ingest_video(
+                &pool,
+                youtube.as_ref(),
+                caption_source.as_ref(),
+                &job.youtube_id,
+                &options,
+            )
+            .await
+        })
+        .await
+        .unwrap_or_else(|e| Err(IngestError::Panicked(e.to_string())));
+
+        // Out of quota: put the job back to try again once the quota resets
+        let resets_at = match &result {
+            Err(IngestError::YouTube(YouTubeApiError::BudgetExhausted(resets_at))) => {
+                Some(*resets_at)
+            }
+            // If we can't work out when the quota resets, try again in an hour
+            Err(IngestError::YouTube(YouTubeApiError::QuotaExceeded(_))) => Some(
+                quota_resets_at(&self.pool)
+                    .await
+                    .unwrap_or_else(|_| Utc::now() + chrono::Duration::hours(1)),
+            ),
+            _ => None,
+        };
+        if let Some(resets_at) = resets_at {
+            *self.paused_until.lock().unwrap() = Some(resets_at);
+            if let Err(e) = requeue_job(&self.pool, job.id).await {
+                eprintln!("Could not requeue ingestion job {}: {e}", job.id);
+            }
+            return;
+        }
+
+        if let Err(e) = finish_job(&self.pool, job.id, &result).await {
+            eprintln!(
+                "Could not record the result of ingestion job {}: {e}",
+                job.id
+            );
+        }
+    }
+
+    // When jobs shouldn't be claimed because we're out of quota, the time
+    // they can be again
+    async fn deferred_until(&self) -> Option<DateTime<Utc>> {
+        let paused_until = *self.paused_until.lock().unwrap();
+        if let Some(paused_until) = paused_until.filter(|t| *t > Utc::now()) {
+            return Some(paused_until);
+        }
+
+        match self.quota.as_ref()?.exhausted_until().await {
+            Ok(exhausted_until) => exhausted_until,
+            Err(e) => {
+                eprintln!("Could not check the Data API quota: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct ClaimedJob {
+    id: i32,
+    youtube_id: String,
+    caption_preferences: sqlx::types::Json<Vec<CaptionPreference>>,
+    refresh: bool,
+}
+
+// Marks the oldest queued job (or abandoned one, see `JOB_LEASE`) as running
+// and hands it back. `skip locked` lets several workers claim jobs at once
+// without picking the same one.
+async fn claim_next_job(pool: &PgPool) -> Result<Option<ClaimedJob>, Error> {
+    sqlx::query_as::<_, ClaimedJob>(
+        "update ingestion_jobs set status='running', started_at=now()
+        where id = (
+            select id from ingestion_jobs
+            where status='queued'
+            or (status='running' and started_at < now() - $1 * interval '1 second')
+            order by id
+            limit 1
+            for update skip locked
+        )
+        returning id, youtube_id, caption_preferences, refresh",
+    )
+    .bind(JOB_LEASE.as_secs_f64())
+    .fetch_optional(pool)
+    .await
+}
+
+async fn requeue_job(pool: &PgPool, job_id: i32) -> Result<(), Error> {
+    sqlx::query("update ingestion_jobs set status='queued', started_at=null where id=$1")
+        .bind(job_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn finish_job(
+    pool: &PgPool,
+    job_id: i32,
+    result: &Result<IngestOutcome, IngestError>,
+) -> Result<(), Error> {
+    match result {
+        Ok(outcome) => sqlx::query(
+            "update ingestion_jobs set status='succeeded', video_id=$1, already_existed=$2, finished_at=now() where id=$3",
+        )
+        .bind(outcome.video_id)
+        .bind(outcome.already_existed)
+        .bind(job_id),
+        Err(e) => sqlx::query(
+            "update ingestion_jobs set status='failed', error=$1, error_message=$2, finished_at=now() where id=$3",
+        )
+        .bind(e.code())
+        .bind(e.to_string())
+        .bind(job_id),
+    }
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::captions::default_caption_preferences;
+    use crate::utils::youtube_url::ChannelRef;
+    use crate::youtube::{ChannelDetails, VideoDetails, YouTubeApiError};
+
+    fn options() -> IngestOptions {
+        IngestOptions {
+            caption_preferences: default_caption_preferences(),
+            refresh: false,
+        }
+    }
+
+    #[sqlx::test]
+    async fn jobs_are_claimed_oldest_first_and_once(pool: PgPool) {
+        let queue = JobQueue::new(pool.clone());
+        let first = queue
+            .enqueue_video("dQw4w9WgXcQ", &options())
+            .await
+            .unwrap();
+        let second = queue
+            .enqueue_video("jNQXAC9IVRw", &options())
+            .await
+            .unwrap();
+
+        let claimed = claim_next_job(&pool).await.unwrap().unwrap();
+        assert_eq!(claimed.id, first);
+        assert_eq!(claimed.youtube_id, "dQw4w9WgXcQ");
+        assert_eq!(claimed.caption_preferences.0, default_caption_preferences());
+        assert_eq!(
+            queue.get(first).await.unwrap().unwrap().status,
+            JobStatus::Running
+        );
+
+        assert_eq!(claim_next_job(&pool).await.unwrap().unwrap().id, second);
+        assert!(claim_next_job(&pool).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    async fn failed_jobs_record_the_error(pool: PgPool) {
+        let queue = JobQueue::new(pool.clone());
+        let job_id = queue
+            .enqueue_video("dQw4w9WgXcQ", &options())
+            .await
+            .unwrap();
+        claim_next_job(&pool).await.unwrap();
+
+        let result = Err(IngestError::YouTube(YouTubeApiError::VideoNotFound(
+            "dQw4w9WgXcQ".to_string(),
+        )));
+        finish_job(&pool, job_id, &result).await.unwrap();
+
+        let job = queue.get(job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.error.as_deref(), Some("video_not_found"));
+        assert_eq!(
+            job.error_message.as_deref(),
+            Some("YouTube has no video with id dQw4w9WgXcQ")
+        );
+        assert!(job.finished_at.is_some());
+    }
+
+    #[sqlx::test]
+    async fn abandoned_jobs_are_reclaimed_once_their_lease_runs_out(pool: PgPool) {
+        let queue = JobQueue::new(pool.clone());
+        let job_id = queue
+            .enqueue_video("dQw4w9WgXcQ", &options())
+            .await
+            .unwrap();
+        claim_next_job(&pool).await.unwrap();
+
+        // Still being worked on, maybe by another process
+        assert!(claim_next_job(&pool).await.unwrap().is_none());
+
+        sqlx::query(
+            "update ingestion_jobs set started_at = now() - interval '2 hours' where id=$1",
+        )
+        .bind(job_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert_eq!(claim_next_job(&pool).await.unwrap().unwrap().id, job_id);
+        let job = queue.get(job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Running);
+        assert!(job.started_at.unwrap() > Utc::now() - chrono::Duration::minutes(1));
+    }
+
+    #[sqlx::test]
+    async fn videos_already_queued_are_not_queued_again(pool: PgPool) {
+        let queue = JobQueue::new(pool.clone());
+        let playlist = || vec!["dQw4w9WgXcQ".to_string(), "jNQXAC9IVRw".to_string()];
+
+        let first = queue.enqueue_videos(playlist(), &options()).await.unwrap();
+        assert!(first
+            .iter()
+            .all(|v| v.job_id.is_some() && !v.already_queued));
+
+        // One still queued, one failed
+        let failed = claim_next_job(&pool).await.unwrap().unwrap();
+        let result = Err(IngestError::Panicked("boom".to_string()));
+        finish_job(&pool, failed.id, &result).await.unwrap();
+
+        let second = queue.enqueue_videos(playlist(), &options()).await.unwrap();
+        let queued: Vec<(&str, bool, bool)> = second
+            .iter()
+            .map(|v| (v.youtube_id.as_str(), v.already_queued, v.job_id.is_some()))
+            .collect();
+        assert_eq!(
+            queued,
+            vec![("dQw4w9WgXcQ", false, true), ("jNQXAC9IVRw", true, false)]
+        );
+
+        // Refreshing doesn't queue a second job either
+        let refresh = IngestOptions {
+            refresh: true,
+            ..options()
+        };
+        let third = queue.enqueue_videos(playlist(), &refresh).await.unwrap();
+        assert!(third.iter().all(|v| v.already_queued && v.job_id.is_none()));
+
+        let jobs: i64 = sqlx::query_scalar("select count(*) from ingestion_jobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(jobs, 3);
+    }
+
+    // A Data API that's always out of quota
+    struct OutOfQuota(Arc<Mutex<usize>>);
+
+    #[rocket::async_trait]
+    impl YouTubeApi for OutOfQuota {
+        async fn videos(&self, _: &[String]) -> Result<Vec<VideoDetails>, YouTubeApiError> {
+            *self.0.lock().unwrap() += 1;
+            Err(YouTubeApiError::QuotaExceeded("quota".to_string()))
+        }
+
+        async fn channel(&self, _: &ChannelRef) -> Result<ChannelDetails, YouTubeApiError> {
+            Err(YouTubeApiError::Api(
+                0,
+                "not used by these tests".to_string(),
+            ))
+        }
+
+        async fn playlist_video_ids(&self, _: &str) -> Result<Vec<String>, YouTubeApiError> {
+            Err(YouTubeApiError::Api(
+                0,
+                "not used by these tests".to_string(),
+            ))
+        }
+    }
+
+    #[sqlx::test]
+    async fn jobs_wait_out_an_exceeded_quota(pool: PgPool) {
+        let queue = JobQueue::new(pool.clone());
+        let job_id = queue
+            .enqueue_video("dQw4w9WgXcQ", &options())
+            .await
+            .unwrap();
+
+        let calls = Arc::new(Mutex::new(0));
+        let caption_source: Arc<dyn CaptionSource> =
+            Arc::new(crate::utils::caption_source::FileCaptionSource::new("."));
+        queue.start_workers(Arc::new(OutOfQuota(calls.clone())), caption_source, 1);
+
+        for _ in 0..50 {
+            if queue.deferred_until().await.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // Back in the queue rather than failed, and not retried until the
+        // quota resets
+        let job = queue.get(job_id).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Queued);
+        assert!(queue.deferred_until().await.unwrap() > Utc::now());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[sqlx::test]
+    async fn jobs_wait_while_the_budget_is_spent(pool: PgPool) {
+        let quota = Quota::new(pool.clone(), Some(1));
+        let queue = JobQueue::new(pool.clone()).with_quota(quota.clone());
+        assert!(queue.deferred_until().await.is_none());
+
+        quota.spend(crate::quota::ApiCall::Videos).await.unwrap();
+
+        assert_eq!(
+            queue.deferred_until().await,
+            Some(quota.resets_at().await.unwrap())
+        );
+    }
+}
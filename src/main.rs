@@ -2,18 +2,33 @@
 extern crate rocket;
 extern crate dotenv;
 
+mod channels;
+mod commands;
 mod cors;
 mod endpoints;
+mod ingest;
+mod jobs;
+mod quota;
 mod utils;
+mod youtube;
 
 use cors::CORS;
 use dotenv::dotenv;
 use endpoints::general::ApiState;
+use jobs::JobQueue;
+use quota::{Quota, DEFAULT_DAILY_BUDGET};
 use sqlx::postgres::PgPoolOptions;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use utils::caption_source::{CaptionSource, FileCaptionSource, YouTubeCaptionSource};
+use utils::environment::get_env;
+use utils::feed::DEFAULT_FEED_URL;
+use utils::http::HttpClient;
+use youtube::{DataApiClient, YouTubeApi, DEFAULT_API_URL};
 
-#[launch]
-async fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
     dotenv().ok();
 
     let db_url = env::var("DATABASE_URL").unwrap();
@@ -24,8 +39,78 @@ async fn rocket() -> _ {
         .await
         .expect("Unable to connect to Postgres");
 
-    rocket::build()
-        .manage(ApiState { pool })
+    let http = HttpClient::new().expect("Unable to build the HTTP client");
+
+    // Data API calls stop for the day once YOUTUBE_DAILY_QUOTA units have
+    // been spent, and ingestion jobs wait until the quota resets
+    let daily_budget = env::var("YOUTUBE_DAILY_QUOTA")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_DAILY_BUDGET);
+    let quota = Quota::new(pool.clone(), Some(daily_budget));
+
+    // YOUTUBE_API_URL can point Data API calls somewhere other than Google
+    let youtube_api_url =
+        env::var("YOUTUBE_API_URL").unwrap_or_else(|_| DEFAULT_API_URL.to_string());
+    let youtube: Arc<dyn YouTubeApi> = Arc::new(
+        DataApiClient::new(http.clone(), get_env("YOUTUBE_API_KEY"), youtube_api_url)
+            .with_quota(quota.clone()),
+    );
+
+    // `cargo run -- <command>` runs a maintenance command instead of the server
+    let command = env::args().nth(1);
+    if let Some(command) = command {
+        if let Err(e) = commands::run(&command, &pool, youtube.as_ref()).await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    // Point CAPTION_FIXTURES_DIR at a directory of saved watch pages to ingest
+    // captions without talking to YouTube
+    let caption_source: Arc<dyn CaptionSource> = match env::var("CAPTION_FIXTURES_DIR") {
+        Ok(dir) => Arc::new(FileCaptionSource::new(dir)),
+        Err(_) => Arc::new(YouTubeCaptionSource::new(http.clone())),
+    };
+
+    // Videos are ingested in the background by INGEST_WORKERS tasks
+    let workers = env::var("INGEST_WORKERS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(4);
+    let state = ApiState {
+        jobs: JobQueue::new(pool.clone()).with_quota(quota.clone()),
+        pool,
+        youtube,
+        quota,
+    };
+    state
+        .jobs
+        .start_workers(state.youtube.clone(), caption_source, workers);
+
+    // Tracked channels' feeds are checked for new uploads every
+    // CHANNEL_SYNC_INTERVAL_MINUTES. YOUTUBE_FEED_URL can point the poller
+    // somewhere other than YouTube. The interval is at least a minute; tokio
+    // won't tick on a zero interval.
+    let sync_interval = env::var("CHANNEL_SYNC_INTERVAL_MINUTES")
+        .ok()
+        .and_then(|n| n.parse::<u64>().ok())
+        .unwrap_or(15)
+        .max(1);
+    let feed_base_url =
+        env::var("YOUTUBE_FEED_URL").unwrap_or_else(|_| DEFAULT_FEED_URL.to_string());
+    channels::start_channel_sync(
+        state.pool.clone(),
+        http,
+        state.jobs.clone(),
+        feed_base_url,
+        Duration::from_secs(sync_interval * 60),
+    );
+
+    let result = rocket::build()
+        .manage(state)
         .attach(CORS)
         .mount(
             "/",
@@ -40,6 +125,19 @@ async fn rocket() -> _ {
                 endpoints::videos::create_video,
                 endpoints::videos::search_video_captions,
                 endpoints::videos::test_video,
+                endpoints::transcripts::import_transcript,
+                endpoints::transcripts::export_transcript,
+                endpoints::jobs::get_job,
+                endpoints::playlists::create_playlist,
+                endpoints::channels::create_channel,
+                endpoints::admin::get_quota,
             ],
         )
+        .launch()
+        .await;
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 }
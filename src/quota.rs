@@ -0,0 +1,327 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::serde::Serialize;
+use sqlx::{Error, FromRow, PgPool};
+use std::fmt;
+
+// The Data API quota resets at midnight Pacific time, so that's the day usage
+// is counted against
+const QUOTA_DAY: &str = "(now() at time zone 'America/Los_Angeles')::date";
+
+// Google's default daily quota for a project
+pub const DEFAULT_DAILY_BUDGET: i64 = 10_000;
+
+// The Data API methods we budget for, all `list` calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiCall {
+    Videos,
+    Channels,
+    PlaylistItems,
+    Search,
+}
+
+impl ApiCall {
+    pub const ALL: [ApiCall; 4] = [
+        ApiCall::Videos,
+        ApiCall::Channels,
+        ApiCall::PlaylistItems,
+        ApiCall::Search,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApiCall::Videos => "videos.list",
+            ApiCall::Channels => "channels.list",
+            ApiCall::PlaylistItems => "playlistItems.list",
+            ApiCall::Search => "search.list",
+        }
+    }
+
+    // The path under the Data API's base url
+    pub fn resource(&self) -> &'static str {
+        match self {
+            ApiCall::Videos => "videos",
+            ApiCall::Channels => "channels",
+            ApiCall::PlaylistItems => "playlistItems",
+            ApiCall::Search => "search",
+        }
+    }
+
+    // What each request costs, per
+    // https://developers.google.com/youtube/v3/determine_quota_cost
+    pub fn units(&self) -> i64 {
+        match self {
+            ApiCall::Search => 100,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum QuotaError {
+    // Spending more would go over the daily budget
+    Exhausted { resets_at: DateTime<Utc> },
+    Database(Error),
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaError::Exhausted { resets_at } => write!(
+                f,
+                "Today's YouTube Data API budget is used up; it resets at {resets_at}"
+            ),
+            QuotaError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for QuotaError {}
+
+impl From<Error> for QuotaError {
+    fn from(e: Error) -> Self {
+        QuotaError::Database(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct CallUsage {
+    pub call_type: String,
+    pub calls: i64,
+    pub units: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaUsage {
+    // In Pacific time
+    pub day: NaiveDate,
+    pub used: i64,
+    pub budget: Option<i64>,
+    pub remaining: Option<i64>,
+    pub resets_at: DateTime<Utc>,
+    pub calls: Vec<CallUsage>,
+}
+
+// When the current quota day ends
+pub async fn quota_resets_at(pool: &PgPool) -> Result<DateTime<Utc>, Error> {
+    sqlx::query_scalar(&format!(
+        "select ({QUOTA_DAY} + 1)::timestamp at time zone 'America/Los_Angeles'"
+    ))
+    .fetch_one(pool)
+    .await
+}
+
+// Keeps count of the Data API units we've spent each day in `api_quota_usage`,
+// and stops us spending more than `daily_budget` (when there is one)
+#[derive(Debug, Clone)]
+pub struct Quota {
+    pool: PgPool,
+    daily_budget: Option<i64>,
+}
+
+impl Quota {
+    pub fn new(pool: PgPool, daily_budget: Option<i64>) -> Quota {
+        Quota { pool, daily_budget }
+    }
+
+    // Records a call we're about to make, or refuses it if it would take us
+    // over budget. Calls are counted up front since Google charges for
+    // failed requests too.
+    pub async fn spend(&self, call: ApiCall) -> Result<(), QuotaError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Each call type has its own row, so without this two workers spending
+        // at once could both see room for one more call and together go over
+        if self.daily_budget.is_some() {
+            sqlx::query(&format!(
+                "select pg_advisory_xact_lock(hashtext('api_quota_usage'), {QUOTA_DAY} - date '2000-01-01')"
+            ))
+            .execute(&mut tx)
+            .await?;
+        }
+
+        let spent: Option<i64> = sqlx::query_scalar(&format!(
+            "insert into api_quota_usage (day, call_type, calls, units)
+            select {QUOTA_DAY}, $1, 1, $2
+            where $3::bigint is null
+                or (select coalesce(sum(units), 0) from api_quota_usage where day = {QUOTA_DAY}) + $2 <= $3
+            on conflict (day, call_type) do update set calls = api_quota_usage.calls + 1, units = api_quota_usage.units + excluded.units
+            returning units"
+        ))
+        .bind(call.name())
+        .bind(call.units())
+        .bind(self.daily_budget)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        match spent {
+            Some(_) => Ok(()),
+            None => Err(QuotaError::Exhausted {
+                resets_at: self.resets_at().await?,
+            }),
+        }
+    }
+
+    pub async fn resets_at(&self) -> Result<DateTime<Utc>, Error> {
+        quota_resets_at(&self.pool).await
+    }
+
+    pub async fn usage(&self) -> Result<QuotaUsage, Error> {
+        let (day, resets_at): (NaiveDate, DateTime<Utc>) = sqlx::query_as(&format!(
+            "select {QUOTA_DAY}, ({QUOTA_DAY} + 1)::timestamp at time zone 'America/Los_Angeles'"
+        ))
+        .fetch_one(&self.pool)
+        .await?;
+
+        let spent = sqlx::query_as::<_, CallUsage>(
+            "select call_type, calls, units from api_quota_usage where day=$1",
+        )
+        .bind(day)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Every call type we know about, whether or not we've made one today
+        let calls: Vec<CallUsage> = ApiCall::ALL
+            .iter()
+            .map(|call| {
+                spent
+                    .iter()
+                    .find(|usage| usage.call_type == call.name())
+                    .cloned()
+                    .unwrap_or(CallUsage {
+                        call_type: call.name().to_string(),
+                        calls: 0,
+                        units: 0,
+                    })
+            })
+            .collect();
+        let used = calls.iter().map(|usage| usage.units).sum();
+
+        Ok(QuotaUsage {
+            day,
+            used,
+            budget: self.daily_budget,
+            remaining: self.daily_budget.map(|budget| (budget - used).max(0)),
+            resets_at,
+            calls,
+        })
+    }
+
+    // When today's budget is all spent, the time it resets
+    pub async fn exhausted_until(&self) -> Result<Option<DateTime<Utc>>, Error> {
+        let usage = self.usage().await?;
+
+        Ok(match usage.remaining {
+            Some(0) => Some(usage.resets_at),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn counts_units_per_call_type(pool: PgPool) {
+        let quota = Quota::new(pool, None);
+
+        quota.spend(ApiCall::Videos).await.unwrap();
+        quota.spend(ApiCall::Videos).await.unwrap();
+        quota.spend(ApiCall::Search).await.unwrap();
+
+        let usage = quota.usage().await.unwrap();
+        assert_eq!(usage.used, 102);
+        assert_eq!(usage.remaining, None);
+        assert_eq!(
+            usage.calls,
+            vec![
+                CallUsage {
+                    call_type: "videos.list".to_string(),
+                    calls: 2,
+                    units: 2,
+                },
+                CallUsage {
+                    call_type: "channels.list".to_string(),
+                    calls: 0,
+                    units: 0,
+                },
+                CallUsage {
+                    call_type: "playlistItems.list".to_string(),
+                    calls: 0,
+                    units: 0,
+                },
+                CallUsage {
+                    call_type: "search.list".to_string(),
+                    calls: 1,
+                    units: 100,
+                },
+            ]
+        );
+        assert!(usage.resets_at > Utc::now());
+    }
+
+    #[sqlx::test]
+    async fn refuses_calls_over_budget(pool: PgPool) {
+        let quota = Quota::new(pool, Some(100));
+
+        // Too expensive to fit, but cheaper calls still do
+        quota.spend(ApiCall::Videos).await.unwrap();
+        assert!(matches!(
+            quota.spend(ApiCall::Search).await,
+            Err(QuotaError::Exhausted { .. })
+        ));
+        assert_eq!(quota.exhausted_until().await.unwrap(), None);
+
+        for _ in 0..99 {
+            quota.spend(ApiCall::Channels).await.unwrap();
+        }
+        let resets_at = quota.resets_at().await.unwrap();
+        assert!(matches!(
+            quota.spend(ApiCall::Videos).await,
+            Err(QuotaError::Exhausted { resets_at: r }) if r == resets_at
+        ));
+        assert_eq!(quota.exhausted_until().await.unwrap(), Some(resets_at));
+
+        let usage = quota.usage().await.unwrap();
+        assert_eq!((usage.used, usage.remaining), (100, Some(0)));
+    }
+
+    #[sqlx::test]
+    async fn concurrent_calls_stay_within_budget(pool: PgPool) {
+        let quota = Quota::new(pool, Some(10));
+
+        let spends: Vec<_> = (0..40)
+            .map(|i| {
+                let quota = quota.clone();
+                let call = ApiCall::ALL[i % 3];
+                tokio::spawn(async move { quota.spend(call).await.is_ok() })
+            })
+            .collect();
+        let mut spent = 0;
+        for spend in spends {
+            if spend.await.unwrap() {
+                spent += 1;
+            }
+        }
+
+        assert_eq!(spent, 10);
+        assert_eq!(quota.usage().await.unwrap().used, 10);
+    }
+
+    #[sqlx::test]
+    async fn earlier_days_do_not_count(pool: PgPool) {
+        sqlx::query(&format!(
+            "insert into api_quota_usage (day, call_type, calls, units) values ({QUOTA_DAY} - 1, 'search.list', 100, 10000)"
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+        let quota = Quota::new(pool, Some(DEFAULT_DAILY_BUDGET));
+
+        quota.spend(ApiCall::PlaylistItems).await.unwrap();
+
+        assert_eq!(quota.usage().await.unwrap().used, 1);
+    }
+}
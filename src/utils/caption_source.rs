@@ -0,0 +1,176 @@
+use super::captions::{CaptionError, YouTubeCaptionTrack};
+use super::http::HttpClient;
+use super::timedtext::TimedTextFormat;
+use reqwest::StatusCode;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use url::Url;
+
+// Where the watch page and timedtext documents for a video come from. The
+// scraper talks to YouTube; the file source reads pages saved to disk, which is
+// what we use in air-gapped environments and tests.
+#[rocket::async_trait]
+pub trait CaptionSource: Send + Sync {
+    async fn fetch_watch_page(&self, video_id: &str) -> Result<String, CaptionError>;
+
+    // Returns `None` when the track isn't available in the requested format.
+    // Anything else going wrong is an error, so a throttled request doesn't
+    // look like a missing track.
+    async fn fetch_caption_track(
+        &self,
+        video_id: &str,
+        track: &YouTubeCaptionTrack,
+        format: TimedTextFormat,
+    ) -> Result<Option<String>, CaptionError>;
+}
+
+pub struct YouTubeCaptionSource {
+    http: HttpClient,
+}
+
+impl YouTubeCaptionSource {
+    pub fn new(http: HttpClient) -> YouTubeCaptionSource {
+        YouTubeCaptionSource { http }
+    }
+}
+
+#[rocket::async_trait]
+impl CaptionSource for YouTubeCaptionSource {
+    async fn fetch_watch_page(&self, video_id: &str) -> Result<String, CaptionError> {
+        let url = format!("https://www.youtube.com/watch?v={video_id}");
+
+        Ok(self.http.get(url).await?.text().await?)
+    }
+
+    async fn fetch_caption_track(
+        &self,
+        _video_id: &str,
+        track: &YouTubeCaptionTrack,
+        format: TimedTextFormat,
+    ) -> Result<Option<String>, CaptionError> {
+        let mut url = Url::parse(&track.base_url)
+            .map_err(|e| CaptionError::MalformedJson(format!("invalid caption url: {e}")))?;
+
+        // The base url normally has no `fmt`, but swap it out if it does
+        let query: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != "fmt")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        {
+            let mut query_pairs = url.query_pairs_mut();
+            query_pairs.clear().extend_pairs(query);
+            if let Some(fmt) = format.fmt_param() {
+                query_pairs.append_pair("fmt", fmt);
+            }
+        }
+
+        let response = self.http.get(url).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(Some(response.error_for_status()?.text().await?))
+    }
+}
+
+// Reads captions from a directory laid out like:
+//
+//   <dir>/<video_id>.html              the saved watch page
+//   <dir>/<video_id>.<lang>.<fmt>      a manually written track
+//   <dir>/<video_id>.<lang>.asr.<fmt>  an auto-generated track
+//
+// where <fmt> is `json3`, `srv3` or `xml`.
+pub struct FileCaptionSource {
+    pub dir: PathBuf,
+}
+
+impl FileCaptionSource {
+    pub fn new(dir: impl Into<PathBuf>) -> FileCaptionSource {
+        FileCaptionSource { dir: dir.into() }
+    }
+
+    fn track_path(
+        &self,
+        video_id: &str,
+        track: &YouTubeCaptionTrack,
+        format: TimedTextFormat,
+    ) -> PathBuf {
+        let extension = format.extension();
+        let file_name = match track.kind.as_deref() {
+            Some(kind) => format!("{video_id}.{}.{kind}.{extension}", track.language_code),
+            None => format!("{video_id}.{}.{extension}", track.language_code),
+        };
+
+        self.dir.join(file_name)
+    }
+}
+
+#[rocket::async_trait]
+impl CaptionSource for FileCaptionSource {
+    async fn fetch_watch_page(&self, video_id: &str) -> Result<String, CaptionError> {
+        let path = self.dir.join(format!("{video_id}.html"));
+
+        // No saved page is the offline equivalent of YouTube not knowing the video
+        tokio::fs::read_to_string(path)
+            .await
+            .map_err(|_| CaptionError::VideoUnavailable(video_id.to_string()))
+    }
+
+    async fn fetch_caption_track(
+        &self,
+        video_id: &str,
+        track: &YouTubeCaptionTrack,
+        format: TimedTextFormat,
+    ) -> Result<Option<String>, CaptionError> {
+        match tokio::fs::read_to_string(self.track_path(video_id, track, format)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::http::RetryPolicy;
+    use crate::utils::test_server::{response, serve};
+    use std::time::Duration;
+
+    async fn fetch(status: &'static str) -> Result<Option<String>, CaptionError> {
+        let (base_url, _) = serve(move |_| response(status, &[], "<timedtext/>")).await;
+        let http = HttpClient::with_retry_policy(RetryPolicy {
+            max_retries: 0,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        })
+        .unwrap();
+        let track = YouTubeCaptionTrack {
+            base_url: format!("{base_url}/api/timedtext?v=abc&lang=en"),
+            language_code: "en".to_string(),
+            kind: None,
+        };
+
+        YouTubeCaptionSource::new(http)
+            .fetch_caption_track("abc", &track, TimedTextFormat::Srv3)
+            .await
+    }
+
+    #[tokio::test]
+    async fn only_not_found_means_unavailable() {
+        assert_eq!(
+            fetch("200 OK").await.unwrap(),
+            Some("<timedtext/>".to_string())
+        );
+        assert_eq!(fetch("404 Not Found").await.unwrap(), None);
+        assert!(matches!(
+            fetch("429 Too Many Requests").await,
+            Err(CaptionError::Request(_))
+        ));
+        assert!(matches!(
+            fetch("503 Service Unavailable").await,
+            Err(CaptionError::Request(_))
+        ));
+    }
+}
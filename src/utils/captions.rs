@@ -1,17 +1,17 @@
-use html_entities::decode_html_entities;
+use super::caption_source::CaptionSource;
+use super::timedtext::{parse_timed_text, TimedTextFormat};
 use rocket::serde::{Deserialize, Serialize};
-use std::io::BufReader;
-use xml::reader::{EventReader, XmlEvent};
+use std::fmt;
 
 #[derive(Debug, Deserialize)]
 pub struct YouTubeHtmlCaptionData {
     #[serde(rename = "playerCaptionsTracklistRenderer")]
-    pub player_captions_tracklist_renderer: YouTubeCaptionTracks,
+    pub player_captions_tracklist_renderer: Option<YouTubeCaptionTracks>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct YouTubeCaptionTracks {
-    #[serde(rename = "captionTracks")]
+    #[serde(rename = "captionTracks", default)]
     pub caption_tracks: Vec<YouTubeCaptionTrack>,
 }
 
@@ -19,102 +19,314 @@ pub struct YouTubeCaptionTracks {
 pub struct YouTubeCaptionTrack {
     #[serde(rename = "baseUrl")]
     pub base_url: String,
+    #[serde(rename = "languageCode")]
+    pub language_code: String,
+    // Only present (as "asr") on auto-generated tracks
+    pub kind: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl YouTubeCaptionTrack {
+    pub fn caption_kind(&self) -> CaptionKind {
+        match self.kind.as_deref() {
+            Some("asr") => CaptionKind::Asr,
+            _ => CaptionKind::Manual,
+        }
+    }
+
+    // "en" matches "en", "en-US", "en-GB", etc.
+    fn matches_language(&self, language: &str) -> bool {
+        let code = self.language_code.to_lowercase();
+        let language = language.to_lowercase();
+
+        code == language || code.split('-').next() == Some(language.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptionKind {
+    Manual,
+    Asr,
+    // Uploaded by a user (srt/vtt) rather than pulled from YouTube
+    Imported,
+}
+
+impl CaptionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaptionKind::Manual => "manual",
+            CaptionKind::Asr => "asr",
+            CaptionKind::Imported => "imported",
+        }
+    }
+}
+
+// One entry in a caption preference list. Missing fields match anything, so
+// `{ "kind": "manual" }` means "any manually written track".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptionPreference {
+    pub language: Option<String>,
+    pub kind: Option<CaptionKind>,
+}
+
+impl CaptionPreference {
+    fn matches(&self, track: &YouTubeCaptionTrack) -> bool {
+        let language_matches = match &self.language {
+            Some(language) => track.matches_language(language),
+            None => true,
+        };
+        let kind_matches = match self.kind {
+            Some(kind) => track.caption_kind() == kind,
+            None => true,
+        };
+
+        language_matches && kind_matches
+    }
+}
+
+// Used when the caller doesn't say which tracks they want: every track, with
+// English written by a human first, then English ASR, then everything else.
+pub fn default_caption_preferences() -> Vec<CaptionPreference> {
+    vec![
+        CaptionPreference {
+            language: Some("en".to_string()),
+            kind: Some(CaptionKind::Manual),
+        },
+        CaptionPreference {
+            language: Some("en".to_string()),
+            kind: Some(CaptionKind::Asr),
+        },
+        CaptionPreference {
+            language: None,
+            kind: Some(CaptionKind::Manual),
+        },
+        CaptionPreference {
+            language: None,
+            kind: None,
+        },
+    ]
+}
+
+// Returns every track matching at least one preference, ordered by the
+// earliest preference each one matches
+pub fn select_caption_tracks<'a>(
+    tracks: &'a [YouTubeCaptionTrack],
+    preferences: &[CaptionPreference],
+) -> Vec<&'a YouTubeCaptionTrack> {
+    let mut ranked_tracks: Vec<(usize, &YouTubeCaptionTrack)> = tracks
+        .iter()
+        .filter_map(|track| {
+            preferences
+                .iter()
+                .position(|preference| preference.matches(track))
+                .map(|rank| (rank, track))
+        })
+        .collect();
+    ranked_tracks.sort_by_key(|(rank, _)| *rank);
+
+    ranked_tracks.into_iter().map(|(_, track)| track).collect()
+}
+
+// Maps a caption language code onto the Postgres text search configuration
+// used to index it. Languages Postgres doesn't stem fall back to `simple`.
+pub fn text_search_config(language: &str) -> &'static str {
+    let language = language.to_lowercase();
+
+    match language.split('-').next().unwrap_or_default() {
+        "ar" => "arabic",
+        "ca" => "catalan",
+        "da" => "danish",
+        "de" => "german",
+        "el" => "greek",
+        "en" => "english",
+        "es" => "spanish",
+        "eu" => "basque",
+        "fi" => "finnish",
+        "fr" => "french",
+        "ga" => "irish",
+        "hi" => "hindi",
+        "hu" => "hungarian",
+        "hy" => "armenian",
+        "id" => "indonesian",
+        "it" => "italian",
+        "lt" => "lithuanian",
+        "nb" | "nn" | "no" => "norwegian",
+        "ne" => "nepali",
+        "nl" => "dutch",
+        "pt" => "portuguese",
+        "ro" => "romanian",
+        "ru" => "russian",
+        "sr" => "serbian",
+        "sv" => "swedish",
+        "ta" => "tamil",
+        "tr" => "turkish",
+        "yi" => "yiddish",
+        _ => "simple",
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct YouTubeCaptionTextSnippet {
     pub text: String,
     pub start: f32,
     pub duration: f32,
+    // Only available when the track came from a format with word-level timing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub words: Option<Vec<YouTubeCaptionWord>>,
 }
 
-pub async fn fetch_captions(video_id: String) -> Vec<YouTubeCaptionTextSnippet> {
-    let mut captions_list: Vec<YouTubeCaptionTextSnippet> = vec![];
-    let url = format!("https://www.youtube.com/watch?v={video_id}");
-    let html = reqwest::get(url).await.unwrap().text().await.unwrap();
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct YouTubeCaptionWord {
+    pub text: String,
+    // Seconds from the start of the caption the word belongs to
+    pub offset: f32,
+}
 
+// The captions pulled from a single track, along with which track it was
+#[derive(Debug, Clone)]
+pub struct FetchedCaptions {
+    pub language: String,
+    pub kind: CaptionKind,
+    pub captions: Vec<YouTubeCaptionTextSnippet>,
+}
+
+// Everything that can go wrong while pulling captions for a video. These
+// mirror the exceptions raised by youtube-transcript-api (see the notes at the
+// bottom of this file).
+#[derive(Debug)]
+pub enum CaptionError {
+    // YouTube served us a recaptcha page instead of the watch page
+    TooManyRequests(String),
+    VideoUnavailable(String),
+    TranscriptsDisabled(String),
+    NoTranscriptAvailable(String),
+    // None of the available tracks matched the requested preferences
+    NoMatchingTranscript(String),
+    MalformedJson(String),
+    MalformedXml(String),
+    Request(reqwest::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for CaptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptionError::TooManyRequests(video_id) => write!(
+                f,
+                "YouTube is rate limiting caption requests (recaptcha page returned for {video_id})"
+            ),
+            CaptionError::VideoUnavailable(video_id) => {
+                write!(f, "Video {video_id} is unavailable")
+            }
+            CaptionError::TranscriptsDisabled(video_id) => {
+                write!(f, "Transcripts are disabled for video {video_id}")
+            }
+            CaptionError::NoTranscriptAvailable(video_id) => {
+                write!(f, "No transcript is available for video {video_id}")
+            }
+            CaptionError::NoMatchingTranscript(video_id) => write!(
+                f,
+                "None of the transcripts for video {video_id} match the requested language/kind"
+            ),
+            CaptionError::MalformedJson(e) => write!(f, "Could not parse caption track list: {e}"),
+            CaptionError::MalformedXml(e) => write!(f, "Could not parse caption XML: {e}"),
+            CaptionError::Request(e) => write!(f, "Request to YouTube failed: {e}"),
+            CaptionError::Io(e) => write!(f, "Could not read captions: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CaptionError {}
+
+impl From<reqwest::Error> for CaptionError {
+    fn from(e: reqwest::Error) -> Self {
+        CaptionError::Request(e)
+    }
+}
+
+impl From<std::io::Error> for CaptionError {
+    fn from(e: std::io::Error) -> Self {
+        CaptionError::Io(e)
+    }
+}
+
+// Pulls the caption track list out of the watch page html
+pub fn extract_caption_tracks(
+    html: &str,
+    video_id: &str,
+) -> Result<YouTubeCaptionTracks, CaptionError> {
     let data: Vec<&str> = html.split("\"captions\":").collect();
 
-    if data.len() > 1 {
-        let sub_snippet = data[1].to_string();
-        let transcript_sub_snippet: Vec<&str> = sub_snippet.split(",\"videoDetails").collect();
-        let transcript_json = transcript_sub_snippet[0];
-        let transcript_data: YouTubeHtmlCaptionData =
-            serde_json::from_str(transcript_json).unwrap();
-
-        // Sometimes there's two caption tracks, sometimes there's 1. We just
-        // grab the last one cause that one seems to work.
-        let transcript_idx = transcript_data
-            .player_captions_tracklist_renderer
-            .caption_tracks
-            .len()
-            - 1;
-
-        let transcript_url = transcript_data
-            .player_captions_tracklist_renderer
-            .caption_tracks[transcript_idx]
-            .base_url
-            .clone();
-
-        let data = reqwest::get(&transcript_url)
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
-
-        // dbg!(&data);
-
-        let reader = EventReader::new(BufReader::new(data.as_bytes()));
-
-        let mut temp_caption = YouTubeCaptionTextSnippet {
-            text: String::new(),
-            start: 0.0,
-            duration: 0.0,
-        };
+    if data.len() <= 1 {
+        if html.contains("class=\"g-recaptcha\"") {
+            return Err(CaptionError::TooManyRequests(video_id.to_string()));
+        }
+        if !html.contains("\"playabilityStatus\":") {
+            return Err(CaptionError::VideoUnavailable(video_id.to_string()));
+        }
 
-        for event in reader {
-            match event {
-                Ok(XmlEvent::StartElement {
-                    name, attributes, ..
-                }) => {
-                    if name.local_name == "text" {
-                        for attr in attributes {
-                            if attr.name.to_string() == "start".to_string() {
-                                temp_caption.start = attr.value.parse::<f32>().unwrap();
-                            } else if attr.name.to_string() == "dur".to_string() {
-                                temp_caption.duration = attr.value.parse::<f32>().unwrap();
-                            }
-                        }
-                    }
-                }
-                Ok(XmlEvent::EndElement { name, .. }) => {
-                    if name.local_name == "text" {
-                        captions_list.push(temp_caption);
-                        temp_caption = YouTubeCaptionTextSnippet {
-                            text: String::new(),
-                            start: 0.0,
-                            duration: 0.0,
-                        };
-                    }
-                }
-                Ok(XmlEvent::Characters(text)) => {
-                    temp_caption.text = decode_html_entities(&text.replace("\n", ""))
-                        .unwrap()
-                        .to_owned();
-                }
-                Err(e) => {
-                    println!("error: {:?}", e);
+        return Err(CaptionError::TranscriptsDisabled(video_id.to_string()));
+    }
+
+    let transcript_sub_snippet: Vec<&str> = data[1].split(",\"videoDetails").collect();
+    let transcript_json = transcript_sub_snippet[0].replace('\n', "");
+    let transcript_data: YouTubeHtmlCaptionData = serde_json::from_str(&transcript_json)
+        .map_err(|e| CaptionError::MalformedJson(e.to_string()))?;
+
+    let tracks = transcript_data
+        .player_captions_tracklist_renderer
+        .ok_or_else(|| CaptionError::TranscriptsDisabled(video_id.to_string()))?;
+
+    if tracks.caption_tracks.is_empty() {
+        return Err(CaptionError::NoTranscriptAvailable(video_id.to_string()));
+    }
+
+    Ok(tracks)
+}
+
+// Fetches every caption track matching the preferences (each language, manual
+// and ASR), in preference order
+pub async fn fetch_captions(
+    source: &dyn CaptionSource,
+    video_id: String,
+    preferences: &[CaptionPreference],
+) -> Result<Vec<FetchedCaptions>, CaptionError> {
+    let html = source.fetch_watch_page(&video_id).await?;
+
+    let tracks = extract_caption_tracks(&html, &video_id)?;
+    let selected_tracks = select_caption_tracks(&tracks.caption_tracks, preferences);
+
+    if selected_tracks.is_empty() {
+        return Err(CaptionError::NoMatchingTranscript(video_id));
+    }
+
+    let mut caption_sets: Vec<FetchedCaptions> = vec![];
+
+    for track in selected_tracks {
+        // Try the formats with word-level timing first, falling back to the
+        // plain xml when a track isn't available in them
+        let mut captions: Option<Vec<YouTubeCaptionTextSnippet>> = None;
+
+        for format in TimedTextFormat::RICHEST_FIRST {
+            if let Some(data) = source.fetch_caption_track(&video_id, track, format).await? {
+                if !data.trim().is_empty() {
+                    captions = Some(parse_timed_text(&data, format)?);
                     break;
                 }
-                _ => {}
             }
         }
-    } else {
-        todo!("Havent handled this yet");
+
+        let captions =
+            captions.ok_or_else(|| CaptionError::NoTranscriptAvailable(video_id.clone()))?;
+
+        caption_sets.push(FetchedCaptions {
+            language: track.language_code.clone(),
+            kind: track.caption_kind(),
+            captions,
+        });
     }
 
-    return captions_list;
+    Ok(caption_sets)
 }
 
 // https://github.com/jdepoix/youtube-transcript-api
@@ -145,3 +357,165 @@ pub async fn fetch_captions(video_id: String) -> Vec<YouTubeCaptionTextSnippet>
 //             raise NoTranscriptAvailable(video_id)
 
 //         return captions_json
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::caption_source::FileCaptionSource;
+
+    fn fixtures() -> FileCaptionSource {
+        FileCaptionSource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/captions"
+        ))
+    }
+
+    fn track(language_code: &str, kind: Option<&str>) -> YouTubeCaptionTrack {
+        YouTubeCaptionTrack {
+            base_url: String::new(),
+            language_code: language_code.to_string(),
+            kind: kind.map(|k| k.to_string()),
+        }
+    }
+
+    #[test]
+    fn extract_caption_tracks_errors() {
+        let recaptcha = r#"<form><div class="g-recaptcha"></div></form>"#;
+        assert!(matches!(
+            extract_caption_tracks(recaptcha, "abc"),
+            Err(CaptionError::TooManyRequests(_))
+        ));
+
+        assert!(matches!(
+            extract_caption_tracks("<html></html>", "abc"),
+            Err(CaptionError::VideoUnavailable(_))
+        ));
+
+        let no_captions = r#"{"playabilityStatus":{"status":"OK"},"videoDetails":{}}"#;
+        assert!(matches!(
+            extract_caption_tracks(no_captions, "abc"),
+            Err(CaptionError::TranscriptsDisabled(_))
+        ));
+
+        let no_renderer = r#""playabilityStatus":{},"captions":{},"videoDetails":{}"#;
+        assert!(matches!(
+            extract_caption_tracks(no_renderer, "abc"),
+            Err(CaptionError::TranscriptsDisabled(_))
+        ));
+
+        let no_tracks = r#""playabilityStatus":{},"captions":{"playerCaptionsTracklistRenderer":{}},"videoDetails":{}"#;
+        assert!(matches!(
+            extract_caption_tracks(no_tracks, "abc"),
+            Err(CaptionError::NoTranscriptAvailable(_))
+        ));
+
+        let broken = r#""playabilityStatus":{},"captions":{"playerCaptions,"videoDetails":{}"#;
+        assert!(matches!(
+            extract_caption_tracks(broken, "abc"),
+            Err(CaptionError::MalformedJson(_))
+        ));
+    }
+
+    #[test]
+    fn select_caption_tracks_by_preference() {
+        let tracks = vec![
+            track("es", None),
+            track("en-US", Some("asr")),
+            track("en", None),
+        ];
+
+        let selected = select_caption_tracks(&tracks, &default_caption_preferences());
+        let selected: Vec<(&str, CaptionKind)> = selected
+            .iter()
+            .map(|t| (t.language_code.as_str(), t.caption_kind()))
+            .collect();
+        assert_eq!(
+            selected,
+            vec![
+                ("en", CaptionKind::Manual),
+                ("en-US", CaptionKind::Asr),
+                ("es", CaptionKind::Manual),
+            ]
+        );
+
+        let asr_only = vec![CaptionPreference {
+            language: None,
+            kind: Some(CaptionKind::Asr),
+        }];
+        let selected = select_caption_tracks(&tracks, &asr_only);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].language_code, "en-US");
+
+        let french = vec![CaptionPreference {
+            language: Some("fr".to_string()),
+            kind: None,
+        }];
+        assert!(select_caption_tracks(&tracks, &french).is_empty());
+    }
+
+    #[test]
+    fn text_search_configs() {
+        assert_eq!(text_search_config("en"), "english");
+        assert_eq!(text_search_config("pt-BR"), "portuguese");
+        assert_eq!(text_search_config("ja"), "simple");
+    }
+
+    #[tokio::test]
+    async fn fetch_captions_from_fixtures() {
+        let caption_sets = fetch_captions(
+            &fixtures(),
+            "fixture0001".to_string(),
+            &default_caption_preferences(),
+        )
+        .await
+        .unwrap();
+
+        let tracks: Vec<(&str, CaptionKind, usize)> = caption_sets
+            .iter()
+            .map(|c| (c.language.as_str(), c.kind, c.captions.len()))
+            .collect();
+        assert_eq!(
+            tracks,
+            vec![
+                ("en", CaptionKind::Manual, 3),
+                ("en", CaptionKind::Asr, 2),
+                ("es", CaptionKind::Manual, 1),
+            ]
+        );
+        assert_eq!(caption_sets[0].captions[1].text, "it's a beautiful day");
+        assert_eq!(caption_sets[0].captions[2].text, "game, set & match");
+        assert!(caption_sets[0].captions[0].words.is_none());
+
+        // The ASR track is only saved as json3, which has word timings
+        let asr_caption = &caption_sets[1].captions[1];
+        assert_eq!(asr_caption.text, "it's a beautiful day");
+        assert_eq!(asr_caption.start, 2.8);
+        let words = asr_caption.words.as_ref().unwrap();
+        assert_eq!(words.len(), 4);
+        assert_eq!(words[2].text, "beautiful");
+        assert_eq!(words[2].offset, 0.56);
+    }
+
+    #[tokio::test]
+    async fn fetch_captions_fixture_errors() {
+        let preferences = default_caption_preferences();
+
+        assert!(matches!(
+            fetch_captions(&fixtures(), "fixture0002".to_string(), &preferences).await,
+            Err(CaptionError::TranscriptsDisabled(_))
+        ));
+        assert!(matches!(
+            fetch_captions(&fixtures(), "missing0000".to_string(), &preferences).await,
+            Err(CaptionError::VideoUnavailable(_))
+        ));
+
+        let german = vec![CaptionPreference {
+            language: Some("de".to_string()),
+            kind: None,
+        }];
+        assert!(matches!(
+            fetch_captions(&fixtures(), "fixture0001".to_string(), &german).await,
+            Err(CaptionError::NoMatchingTranscript(_))
+        ));
+    }
+}
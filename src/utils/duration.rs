@@ -0,0 +1,166 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum DurationError {
+    Invalid(String),
+    // Years and months don't have a fixed length in seconds
+    UnsupportedUnit(String),
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DurationError::Invalid(s) => write!(f, "\"{s}\" is not a valid ISO 8601 duration"),
+            DurationError::UnsupportedUnit(s) => {
+                write!(
+                    f,
+                    "\"{s}\" uses years or months, which can't be converted to seconds"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DurationError {}
+
+// Parses the ISO 8601 durations the Data API returns in
+// `contentDetails.duration` (`PT1H2M3S`, `P1DT2H`, `P0D` for live streams) into
+// whole seconds. Fractional seconds are truncated.
+pub fn parse_iso8601_duration(s: &str) -> Result<i32, DurationError> {
+    let invalid = || DurationError::Invalid(s.to_string());
+
+    let rest = s.strip_prefix('P').ok_or_else(invalid)?;
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let (date_part, time_part) = match rest.split_once('T') {
+        Some((_, "")) => return Err(invalid()),
+        Some((date, time)) => (date, Some(time)),
+        None => (rest, None),
+    };
+
+    let mut seconds: f64 = 0.0;
+
+    for (value, unit) in duration_components(date_part).ok_or_else(invalid)? {
+        seconds += value
+            * match unit {
+                'W' => 604_800.0,
+                'D' => 86_400.0,
+                'Y' | 'M' => return Err(DurationError::UnsupportedUnit(s.to_string())),
+                _ => return Err(invalid()),
+            };
+    }
+
+    if let Some(time_part) = time_part {
+        for (value, unit) in duration_components(time_part).ok_or_else(invalid)? {
+            seconds += value
+                * match unit {
+                    'H' => 3600.0,
+                    'M' => 60.0,
+                    'S' => 1.0,
+                    _ => return Err(invalid()),
+                };
+        }
+    }
+
+    if seconds > i32::MAX as f64 {
+        return Err(invalid());
+    }
+
+    Ok(seconds as i32)
+}
+
+// Splits "1H2M3.5S" into [(1, 'H'), (2, 'M'), (3.5, 'S')], making sure each
+// unit appears at most once
+fn duration_components(s: &str) -> Option<Vec<(f64, char)>> {
+    let mut components: Vec<(f64, char)> = vec![];
+    let mut number = String::new();
+
+    for c in s.chars() {
+        if c.is_ascii_digit() || c == '.' || c == ',' {
+            number.push(if c == ',' { '.' } else { c });
+        } else {
+            if number.is_empty() || components.iter().any(|(_, unit)| *unit == c) {
+                return None;
+            }
+            components.push((number.parse::<f64>().ok()?, c));
+            number.clear();
+        }
+    }
+
+    // A trailing number without a unit
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(components)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("PT0S", 0),
+            ("P0D", 0),
+            ("PT15S", 15),
+            ("PT4M", 240),
+            ("PT4M13S", 253),
+            ("PT1H", 3600),
+            ("PT1H2M3S", 3723),
+            ("PT10H0M1S", 36001),
+            ("PT1H30S", 3630),
+            ("P1D", 86400),
+            ("P1DT2H3M4S", 93784),
+            ("P2W", 1_209_600),
+            ("P1W1DT1S", 691_201),
+            ("PT1.5S", 1),
+            ("PT0,9S", 0),
+            ("PT90M", 5400),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_iso8601_duration(input), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for input in [
+            "",
+            "P",
+            "PT",
+            "1H2M",
+            "PT1H2M3",
+            "PTS",
+            "PT1X",
+            "PT1H1H",
+            "P1DT",
+            "PT-5S",
+            "P1H",
+            "PT1D",
+            "pt1h",
+            "P99999999D",
+        ] {
+            assert_eq!(
+                parse_iso8601_duration(input),
+                Err(DurationError::Invalid(input.to_string())),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_years_and_months() {
+        for input in ["P1Y", "P2M", "P1Y2M3DT4H"] {
+            assert_eq!(
+                parse_iso8601_duration(input),
+                Err(DurationError::UnsupportedUnit(input.to_string())),
+                "{input}"
+            );
+        }
+    }
+}
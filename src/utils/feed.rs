@@ -0,0 +1,141 @@
+use crate::ingest::parse_published_at;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::io::BufReader;
+use xml::reader::{EventReader, XmlEvent};
+
+// Where channel feeds live unless YOUTUBE_FEED_URL says otherwise
+pub const DEFAULT_FEED_URL: &str = "https://www.youtube.com/feeds/videos.xml";
+
+// One `<entry>` from a channel's Atom feed, which lists its 15 latest uploads
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedEntry {
+    pub video_id: String,
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FeedError {
+    MalformedXml(String),
+    MissingVideoId,
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::MalformedXml(e) => write!(f, "Could not parse channel feed: {e}"),
+            FeedError::MissingVideoId => write!(f, "Channel feed entry has no yt:videoId"),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+pub fn feed_url(base_url: &str, channel_youtube_id: &str) -> String {
+    format!("{base_url}?channel_id={channel_youtube_id}")
+}
+
+pub fn parse_channel_feed(data: &str) -> Result<Vec<FeedEntry>, FeedError> {
+    let mut entries: Vec<FeedEntry> = vec![];
+    let reader = EventReader::new(BufReader::new(data.as_bytes()));
+
+    // Only set while we're inside an `<entry>`
+    let mut temp_entry: Option<(Option<String>, Option<DateTime<Utc>>)> = None;
+    let mut text = String::new();
+
+    for e in reader {
+        match e {
+            Ok(XmlEvent::StartElement { name, .. }) => {
+                if name.local_name == "entry" {
+                    temp_entry = Some((None, None));
+                }
+                text.clear();
+            }
+            Ok(XmlEvent::Characters(characters)) => text.push_str(&characters),
+            Ok(XmlEvent::EndElement { name }) => {
+                match (name.local_name.as_str(), &mut temp_entry) {
+                    // `<entry>` has both `<id>yt:video:...</id>` and
+                    // `<yt:videoId>`; the latter is less fiddly
+                    ("videoId", Some((video_id, _))) => *video_id = Some(text.trim().to_string()),
+                    ("published", Some((_, published_at))) => {
+                        *published_at = parse_published_at(text.trim());
+                    }
+                    ("entry", Some(_)) => {
+                        let (video_id, published_at) = temp_entry.take().unwrap_or_default();
+                        entries.push(FeedEntry {
+                            video_id: video_id
+                                .filter(|id| !id.is_empty())
+                                .ok_or(FeedError::MissingVideoId)?,
+                            published_at,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            Err(e) => return Err(FeedError::MalformedXml(e.to_string())),
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_feed_entries() {
+        let feed =
+            std::fs::read_to_string("tests/fixtures/feeds/UCuAXFkgsw1L7xaCfnd5JJOw.xml").unwrap();
+        let entries = parse_channel_feed(&feed).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                FeedEntry {
+                    video_id: "jNQXAC9IVRw".to_string(),
+                    published_at: Some(
+                        DateTime::parse_from_rfc3339("2023-05-01T16:00:05+00:00")
+                            .unwrap()
+                            .with_timezone(&Utc)
+                    ),
+                },
+                FeedEntry {
+                    video_id: "dQw4w9WgXcQ".to_string(),
+                    published_at: Some(
+                        DateTime::parse_from_rfc3339("2009-10-25T06:57:33+00:00")
+                            .unwrap()
+                            .with_timezone(&Utc)
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_empty_feeds() {
+        let feed = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Nobody</title></feed>"#;
+
+        assert_eq!(parse_channel_feed(feed), Ok(vec![]));
+    }
+
+    #[test]
+    fn parse_channel_feed_errors() {
+        let feed = r#"<feed xmlns="http://www.w3.org/2005/Atom"><entry><title>No id</title></entry></feed>"#;
+        assert_eq!(parse_channel_feed(feed), Err(FeedError::MissingVideoId));
+
+        assert!(matches!(
+            parse_channel_feed("<feed><entry>"),
+            Err(FeedError::MalformedXml(_))
+        ));
+    }
+
+    #[test]
+    fn builds_feed_urls() {
+        assert_eq!(
+            feed_url(DEFAULT_FEED_URL, "UCuAXFkgsw1L7xaCfnd5JJOw"),
+            "https://www.youtube.com/feeds/videos.xml?channel_id=UCuAXFkgsw1L7xaCfnd5JJOw"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, IntoUrl, Response, StatusCode};
+use std::future::Future;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// reqwest 0.11 has no separate read timeout, so this covers the whole request
+// including reading the body
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    // The first retry waits up to this long, doubling each time after that
+    pub base_delay: Duration,
+    // Never wait longer than this between attempts. A `Retry-After` asking for
+    // more than this is treated as a failure rather than waited out.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+// The client every outbound YouTube call goes through. It's built once at
+// startup and cloned around (reqwest clients share their connection pool).
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    retry: RetryPolicy,
+}
+
+impl HttpClient {
+    pub fn new() -> Result<HttpClient, reqwest::Error> {
+        HttpClient::with_retry_policy(RetryPolicy::default())
+    }
+
+    pub fn with_retry_policy(retry: RetryPolicy) -> Result<HttpClient, reqwest::Error> {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(HttpClient { client, retry })
+    }
+
+    // Sends a GET, retrying rate limited (429) and server error (5xx)
+    // responses as well as timeouts and network failures. Any other
+    // response is handed back as is, so callers still need to check the
+    // status.
+    pub async fn get(&self, url: impl IntoUrl) -> Result<Response, reqwest::Error> {
+        self.get_with(url, || async { Ok(()) }).await
+    }
+
+    // `get`, calling `before_attempt` ahead of the first request and every
+    // retry. If it fails we give up with its error instead of sending the
+    // request. The Data API client uses this to count each attempt against
+    // its quota, since Google charges for every one.
+    pub async fn get_with<F, Fut, E>(
+        &self,
+        url: impl IntoUrl,
+        mut before_attempt: F,
+    ) -> Result<Response, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: From<reqwest::Error>,
+    {
+        let url = url.into_url()?;
+        let mut attempt = 0;
+
+        loop {
+            before_attempt().await?;
+            let result = self.client.get(url.clone()).send().await;
+            if attempt >= self.retry.max_retries {
+                return Ok(result?);
+            }
+
+            let delay = match &result {
+                Ok(response) if is_retryable_status(response.status()) => {
+                    match retry_after(response) {
+                        Some(delay) if delay > self.retry.max_delay => return Ok(result?),
+                        Some(delay) => delay,
+                        None => self.backoff(attempt),
+                    }
+                }
+                // `is_request` covers connections dropped partway through
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    self.backoff(attempt)
+                }
+                _ => return Ok(result?),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    // Exponential backoff with "full jitter": a random wait between zero and
+    // the capped exponential delay, so retries from several workers spread out
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = backoff_ceiling(&self.retry, attempt);
+
+        rand::thread_rng().gen_range(Duration::ZERO..=delay)
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn backoff_ceiling(retry: &RetryPolicy, attempt: u32) -> Duration {
+    retry
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(retry.max_delay)
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+    parse_retry_after(value, Utc::now())
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+
+    // A date in the past means we can go again straight away
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    // Serves `responses` in order, one per request
+    async fn serve(responses: Vec<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let next = AtomicUsize::new(0);
+        let (base_url, requests) =
+            test_server::serve(move |_| responses[next.fetch_add(1, Ordering::SeqCst)].to_string())
+                .await;
+
+        (format!("{base_url}/"), requests)
+    }
+
+    fn client(max_retries: u32) -> HttpClient {
+        HttpClient::with_retry_policy(RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(2),
+        })
+        .unwrap()
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const RATE_LIMITED: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const SLOW_DOWN: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, requests) = serve(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+
+        let response = client(3).get(&url).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (url, requests) = serve(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+
+        let response = client(1).get(&url).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn honors_retry_after() {
+        let (url, requests) = serve(vec![RATE_LIMITED, OK]).await;
+
+        let started = std::time::Instant::now();
+        let response = client(3).get(&url).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_wait_out_long_retry_afters() {
+        let (url, requests) = serve(vec![SLOW_DOWN, OK]).await;
+
+        let response = client(3).get(&url).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, requests) = serve(vec![NOT_FOUND, OK]).await;
+
+        let response = client(3).get(&url).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_connection_failures() {
+        // Grab a free port, then close it so nothing is listening there
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let err = client(2).get(&url).await.unwrap_err();
+
+        assert!(err.is_connect());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let retry = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(3),
+        };
+
+        let ceilings: Vec<Duration> = (0..5).map(|n| backoff_ceiling(&retry, n)).collect();
+        assert_eq!(
+            ceilings,
+            vec![
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+                Duration::from_secs(3),
+                Duration::from_secs(3),
+            ]
+        );
+        assert_eq!(backoff_ceiling(&retry, 64), Duration::from_secs(3));
+
+        let client = HttpClient::with_retry_policy(retry).unwrap();
+        for attempt in 0..5 {
+            assert!(client.backoff(attempt) <= backoff_ceiling(&client.retry, attempt));
+        }
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
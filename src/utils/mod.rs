@@ -1,2 +1,12 @@
+pub mod caption_source;
 pub mod captions;
+pub mod duration;
 pub mod environment;
+pub mod feed;
+pub mod http;
+pub mod search_query;
+#[cfg(test)]
+pub mod test_server;
+pub mod timedtext;
+pub mod transcripts;
+pub mod youtube_url;
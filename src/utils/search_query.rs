@@ -0,0 +1,176 @@
+use std::fmt;
+
+// Caption searches are handed to Postgres' `websearch_to_tsquery`, which takes
+// the same syntax as most search engines, applied to each caption line:
+//
+//   tennis match        lines with both words
+//   "tennis match"      the words next to each other, in that order
+//   tennis -match       lines with "tennis" but not "match"
+//   tennis OR squash    either word
+//
+// It never fails, quietly dropping anything it can't make sense of, so this
+// checks for the mistakes we'd rather tell people about.
+
+#[derive(Debug, PartialEq)]
+pub enum SearchQueryError {
+    Empty,
+    UnclosedQuote,
+    // `OR` at the start or end, or next to another `OR`
+    DanglingOr,
+    // Everything is excluded, so every caption would match
+    OnlyExclusions,
+    // Every word is one the text search ignores, like "the"
+    NoSearchableWords,
+}
+
+impl fmt::Display for SearchQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchQueryError::Empty => write!(f, "Search text is empty"),
+            SearchQueryError::UnclosedQuote => {
+                write!(f, "Search text has a quote that isn't closed")
+            }
+            SearchQueryError::DanglingOr => {
+                write!(f, "OR needs something to search for on both sides")
+            }
+            SearchQueryError::OnlyExclusions => write!(
+                f,
+                "Search text needs at least one word that isn't excluded with -"
+            ),
+            SearchQueryError::NoSearchableWords => write!(
+                f,
+                "Search text only has words that are too common to search for"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SearchQueryError {}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Term { negated: bool },
+    Or,
+}
+
+pub fn check_search_query(text: &str) -> Result<(), SearchQueryError> {
+    let tokens = tokenize(text)?;
+
+    if tokens.is_empty() {
+        return Err(SearchQueryError::Empty);
+    }
+
+    let mut previous: Option<&Token> = None;
+    for token in &tokens {
+        if *token == Token::Or && matches!(previous, None | Some(Token::Or)) {
+            return Err(SearchQueryError::DanglingOr);
+        }
+        previous = Some(token);
+    }
+    if previous == Some(&Token::Or) {
+        return Err(SearchQueryError::DanglingOr);
+    }
+
+    if !tokens.contains(&Token::Term { negated: false }) {
+        return Err(SearchQueryError::OnlyExclusions);
+    }
+
+    Ok(())
+}
+
+// Splits the text into terms (words or quoted phrases, maybe negated) and
+// ORs. Anything without a letter or digit in it, like "++", is skipped the
+// same way `websearch_to_tsquery` skips it.
+fn tokenize(text: &str) -> Result<Vec<Token>, SearchQueryError> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let negated = c == '-';
+        if negated {
+            chars.next();
+        }
+
+        let mut term = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => term.push(c),
+                    None => return Err(SearchQueryError::UnclosedQuote),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                term.push(c);
+                chars.next();
+            }
+        }
+
+        if term == "OR" && !negated {
+            tokens.push(Token::Or);
+        } else if term.chars().any(char::is_alphanumeric) {
+            tokens.push(Token::Term { negated });
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_search_syntax() {
+        for text in [
+            "tennis",
+            "tennis match ",
+            "\"tennis match\"",
+            "tennis -match",
+            "tennis -\"grand slam\"",
+            "tennis OR squash",
+            "don't",
+            "c++",
+            "tennis ++",
+            "rock and roll",
+        ] {
+            assert_eq!(check_search_query(text), Ok(()), "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_mistakes() {
+        assert_eq!(check_search_query(""), Err(SearchQueryError::Empty));
+        assert_eq!(check_search_query("  ++ "), Err(SearchQueryError::Empty));
+        assert_eq!(
+            check_search_query("\"tennis match"),
+            Err(SearchQueryError::UnclosedQuote)
+        );
+        assert_eq!(
+            check_search_query("OR tennis"),
+            Err(SearchQueryError::DanglingOr)
+        );
+        assert_eq!(
+            check_search_query("tennis OR"),
+            Err(SearchQueryError::DanglingOr)
+        );
+        assert_eq!(
+            check_search_query("tennis OR OR squash"),
+            Err(SearchQueryError::DanglingOr)
+        );
+        assert_eq!(
+            check_search_query("-tennis -\"grand slam\""),
+            Err(SearchQueryError::OnlyExclusions)
+        );
+    }
+}
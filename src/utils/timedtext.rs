@@ -0,0 +1,402 @@
+use super::captions::{CaptionError, YouTubeCaptionTextSnippet, YouTubeCaptionWord};
+use html_entities::decode_html_entities;
+use rocket::serde::Deserialize;
+use std::io::BufReader;
+use xml::reader::{EventReader, XmlEvent};
+
+// The flavours of YouTube's timedtext endpoint we know how to read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimedTextFormat {
+    // `fmt=json3`: events with segments offset in milliseconds
+    Json3,
+    // `fmt=srv3`: `<p t d>` paragraphs with `<s t>` word segments
+    Srv3,
+    // The default `<text start dur>` xml, one timestamp per line
+    Xml,
+}
+
+impl TimedTextFormat {
+    // Formats with word-level timing come first
+    pub const RICHEST_FIRST: [TimedTextFormat; 3] = [
+        TimedTextFormat::Json3,
+        TimedTextFormat::Srv3,
+        TimedTextFormat::Xml,
+    ];
+
+    // The `fmt` query parameter to ask the timedtext endpoint for, if any
+    pub fn fmt_param(&self) -> Option<&'static str> {
+        match self {
+            TimedTextFormat::Json3 => Some("json3"),
+            TimedTextFormat::Srv3 => Some("srv3"),
+            TimedTextFormat::Xml => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TimedTextFormat::Json3 => "json3",
+            TimedTextFormat::Srv3 => "srv3",
+            TimedTextFormat::Xml => "xml",
+        }
+    }
+}
+
+pub fn parse_timed_text(
+    data: &str,
+    format: TimedTextFormat,
+) -> Result<Vec<YouTubeCaptionTextSnippet>, CaptionError> {
+    match format {
+        TimedTextFormat::Json3 => parse_json3(data),
+        TimedTextFormat::Srv3 => parse_srv3(data),
+        TimedTextFormat::Xml => parse_caption_xml(data),
+    }
+}
+
+// Parses the timedtext xml (`<transcript><text start dur>...</text></transcript>`)
+pub fn parse_caption_xml(data: &str) -> Result<Vec<YouTubeCaptionTextSnippet>, CaptionError> {
+    let mut captions_list: Vec<YouTubeCaptionTextSnippet> = vec![];
+    let reader = EventReader::new(BufReader::new(data.as_bytes()));
+
+    let mut temp_caption = YouTubeCaptionTextSnippet::default();
+
+    for event in reader {
+        match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) if name.local_name == "text" => {
+                for attr in attributes {
+                    if attr.name.local_name == "start" {
+                        temp_caption.start = parse_seconds(&attr.value)?;
+                    } else if attr.name.local_name == "dur" {
+                        temp_caption.duration = parse_seconds(&attr.value)?;
+                    }
+                }
+            }
+            Ok(XmlEvent::EndElement { name, .. }) if name.local_name == "text" => {
+                captions_list.push(temp_caption);
+                temp_caption = YouTubeCaptionTextSnippet::default();
+            }
+            Ok(XmlEvent::Characters(text)) => {
+                let text = text.replace('\n', "");
+                temp_caption.text = decode_html_entities(&text).unwrap_or(text);
+            }
+            Err(e) => {
+                return Err(CaptionError::MalformedXml(e.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(captions_list)
+}
+
+// Parses srv3 (`<timedtext format="3">`). Manual tracks have plain text inside
+// each `<p>`, ASR tracks split it into `<s>` segments, one per word.
+pub fn parse_srv3(data: &str) -> Result<Vec<YouTubeCaptionTextSnippet>, CaptionError> {
+    let mut captions_list: Vec<YouTubeCaptionTextSnippet> = vec![];
+    let reader = EventReader::new(BufReader::new(data.as_bytes()));
+
+    let mut temp_caption: Option<YouTubeCaptionTextSnippet> = None;
+    let mut text = String::new();
+    let mut words: Vec<YouTubeCaptionWord> = vec![];
+    let mut temp_word: Option<YouTubeCaptionWord> = None;
+    let mut has_segments = false;
+
+    for event in reader {
+        match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => match name.local_name.as_str() {
+                "p" => {
+                    let mut caption = YouTubeCaptionTextSnippet::default();
+                    for attr in attributes {
+                        if attr.name.local_name == "t" {
+                            caption.start = parse_milliseconds(&attr.value)?;
+                        } else if attr.name.local_name == "d" {
+                            caption.duration = parse_milliseconds(&attr.value)?;
+                        }
+                    }
+                    temp_caption = Some(caption);
+                    text.clear();
+                    words.clear();
+                    has_segments = false;
+                }
+                "s" if temp_caption.is_some() => {
+                    let mut word = YouTubeCaptionWord {
+                        text: String::new(),
+                        offset: 0.0,
+                    };
+                    for attr in attributes {
+                        if attr.name.local_name == "t" {
+                            word.offset = parse_milliseconds(&attr.value)?;
+                        }
+                    }
+                    temp_word = Some(word);
+                    has_segments = true;
+                }
+                "br" => text.push(' '),
+                _ => {}
+            },
+            Ok(XmlEvent::Characters(characters)) | Ok(XmlEvent::Whitespace(characters)) => {
+                if temp_caption.is_some() {
+                    text.push_str(&characters);
+                }
+                if let Some(word) = temp_word.as_mut() {
+                    word.text.push_str(&characters);
+                }
+            }
+            Ok(XmlEvent::EndElement { name, .. }) if name.local_name == "s" => {
+                if let Some(mut word) = temp_word.take() {
+                    word.text = word.text.trim().to_string();
+                    if !word.text.is_empty() {
+                        words.push(word);
+                    }
+                }
+            }
+            Ok(XmlEvent::EndElement { name, .. }) if name.local_name == "p" => {
+                if let Some(mut caption) = temp_caption.take() {
+                    caption.text = collapse_whitespace(&text);
+                    if has_segments {
+                        caption.words = Some(std::mem::take(&mut words));
+                    }
+
+                    // ASR tracks have empty "append" paragraphs between lines
+                    if !caption.text.is_empty() {
+                        captions_list.push(caption);
+                    }
+                }
+            }
+            Err(e) => {
+                return Err(CaptionError::MalformedXml(e.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(captions_list)
+}
+
+#[derive(Debug, Deserialize)]
+struct Json3TimedText {
+    #[serde(default)]
+    events: Vec<Json3Event>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Json3Event {
+    #[serde(rename = "tStartMs", default)]
+    t_start_ms: u64,
+    #[serde(rename = "dDurationMs", default)]
+    d_duration_ms: u64,
+    // Window/style events don't carry any text
+    segs: Option<Vec<Json3Segment>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Json3Segment {
+    #[serde(default)]
+    utf8: String,
+    #[serde(rename = "tOffsetMs")]
+    t_offset_ms: Option<u64>,
+    // Only ASR segments have a confidence score
+    #[serde(rename = "acAsrConf")]
+    ac_asr_conf: Option<u64>,
+}
+
+// Parses json3 (`{ "events": [{ "tStartMs", "dDurationMs", "segs": [...] }] }`).
+// As with srv3, only ASR tracks split their segments up per word.
+pub fn parse_json3(data: &str) -> Result<Vec<YouTubeCaptionTextSnippet>, CaptionError> {
+    let timed_text: Json3TimedText =
+        serde_json::from_str(data).map_err(|e| CaptionError::MalformedJson(e.to_string()))?;
+
+    let mut captions_list: Vec<YouTubeCaptionTextSnippet> = vec![];
+
+    for event in timed_text.events {
+        let segs = match event.segs {
+            Some(segs) => segs,
+            None => continue,
+        };
+
+        let text = collapse_whitespace(&segs.iter().map(|s| s.utf8.as_str()).collect::<String>());
+        if text.is_empty() {
+            continue;
+        }
+
+        let has_word_timing = segs
+            .iter()
+            .any(|s| s.t_offset_ms.is_some() || s.ac_asr_conf.is_some());
+        let words = has_word_timing.then(|| {
+            segs.iter()
+                .filter(|s| !s.utf8.trim().is_empty())
+                .map(|s| YouTubeCaptionWord {
+                    text: s.utf8.trim().to_string(),
+                    offset: s.t_offset_ms.unwrap_or(0) as f32 / 1000.0,
+                })
+                .collect()
+        });
+
+        captions_list.push(YouTubeCaptionTextSnippet {
+            text,
+            start: event.t_start_ms as f32 / 1000.0,
+            duration: event.d_duration_ms as f32 / 1000.0,
+            words,
+        });
+    }
+
+    Ok(captions_list)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn parse_seconds(value: &str) -> Result<f32, CaptionError> {
+    value
+        .parse::<f32>()
+        .map_err(|_| CaptionError::MalformedXml(format!("invalid time value \"{value}\"")))
+}
+
+fn parse_milliseconds(value: &str) -> Result<f32, CaptionError> {
+    value
+        .parse::<u64>()
+        .map(|ms| ms as f32 / 1000.0)
+        .map_err(|_| CaptionError::MalformedXml(format!("invalid time value \"{value}\"")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_caption_xml_decodes_entities() {
+        let xml = r#"<transcript><text start="1.5" dur="2">it&amp;#39;s here</text><text start="3.5" dur="1.25">A &amp;amp; B</text></transcript>"#;
+        let captions = parse_caption_xml(xml).unwrap();
+
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions[0].text, "it's here");
+        assert_eq!(captions[0].start, 1.5);
+        assert_eq!(captions[0].duration, 2.0);
+        assert_eq!(captions[1].text, "A & B");
+    }
+
+    #[test]
+    fn parse_caption_xml_errors() {
+        assert!(matches!(
+            parse_caption_xml(r#"<transcript><text start="abc" dur="2">hi</text></transcript>"#),
+            Err(CaptionError::MalformedXml(_))
+        ));
+        assert!(matches!(
+            parse_caption_xml("<transcript><text>"),
+            Err(CaptionError::MalformedXml(_))
+        ));
+    }
+
+    #[test]
+    fn parse_srv3_with_word_segments() {
+        let srv3 = r#"<?xml version="1.0" encoding="utf-8" ?><timedtext format="3">
+<head><ws id="0"/><wp id="0"/></head>
+<body>
+<w t="0" id="1" wp="0" ws="0"/>
+<p t="160" d="4319" w="1"><s ac="0">we&#39;re</s><s t="480" ac="0"> going</s><s t="1120" ac="0"> to</s></p>
+<p t="2000" w="1" a="1">
+</p>
+<p t="4480" d="2000" w="1"><s ac="0">play</s></p>
+</body>
+</timedtext>"#;
+        let captions = parse_srv3(srv3).unwrap();
+
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions[0].text, "we're going to");
+        assert_eq!(captions[0].start, 0.16);
+        assert_eq!(captions[0].duration, 4.319);
+        assert_eq!(
+            captions[0].words,
+            Some(vec![
+                YouTubeCaptionWord {
+                    text: "we're".to_string(),
+                    offset: 0.0
+                },
+                YouTubeCaptionWord {
+                    text: "going".to_string(),
+                    offset: 0.48
+                },
+                YouTubeCaptionWord {
+                    text: "to".to_string(),
+                    offset: 1.12
+                },
+            ])
+        );
+        assert_eq!(captions[1].text, "play");
+        assert_eq!(captions[1].start, 4.48);
+    }
+
+    #[test]
+    fn parse_srv3_without_word_segments() {
+        let srv3 = r#"<timedtext format="3"><body><p t="1000" d="2500">Line one<br/>and two</p></body></timedtext>"#;
+        let captions = parse_srv3(srv3).unwrap();
+
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "Line one and two");
+        assert_eq!(captions[0].start, 1.0);
+        assert_eq!(captions[0].duration, 2.5);
+        assert_eq!(captions[0].words, None);
+    }
+
+    #[test]
+    fn parse_srv3_errors() {
+        assert!(matches!(
+            parse_srv3(r#"<timedtext><body><p t="soon">hi</p></body></timedtext>"#),
+            Err(CaptionError::MalformedXml(_))
+        ));
+    }
+
+    #[test]
+    fn parse_json3_with_word_segments() {
+        let json3 = r#"{"wireMagic":"pb3","events":[
+            {"tStartMs":0,"dDurationMs":7200,"id":1,"wpWinPosId":1,"wsWinStyleId":1},
+            {"tStartMs":160,"dDurationMs":4319,"wWinId":1,"segs":[{"utf8":"we're","acAsrConf":0},{"utf8":" going","tOffsetMs":480,"acAsrConf":0}]},
+            {"tStartMs":2000,"wWinId":1,"aAppend":1,"segs":[{"utf8":"\n"}]},
+            {"tStartMs":4480,"dDurationMs":2000,"wWinId":1,"segs":[{"utf8":"play","acAsrConf":0}]}
+        ]}"#;
+        let captions = parse_json3(json3).unwrap();
+
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions[0].text, "we're going");
+        assert_eq!(captions[0].start, 0.16);
+        assert_eq!(captions[0].duration, 4.319);
+        assert_eq!(
+            captions[0].words,
+            Some(vec![
+                YouTubeCaptionWord {
+                    text: "we're".to_string(),
+                    offset: 0.0
+                },
+                YouTubeCaptionWord {
+                    text: "going".to_string(),
+                    offset: 0.48
+                },
+            ])
+        );
+        assert_eq!(captions[1].text, "play");
+        assert_eq!(captions[1].words.as_ref().map(|w| w.len()), Some(1));
+    }
+
+    #[test]
+    fn parse_json3_without_word_segments() {
+        let json3 = r#"{"events":[{"tStartMs":1000,"dDurationMs":2500,"segs":[{"utf8":"Line one\nand two"}]}]}"#;
+        let captions = parse_json3(json3).unwrap();
+
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "Line one and two");
+        assert_eq!(captions[0].start, 1.0);
+        assert_eq!(captions[0].words, None);
+    }
+
+    #[test]
+    fn parse_json3_errors() {
+        assert!(matches!(
+            parse_json3(r#"{"events":[{"tStartMs":"soon"}]}"#),
+            Err(CaptionError::MalformedJson(_))
+        ));
+    }
+}
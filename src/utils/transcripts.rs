@@ -0,0 +1,439 @@
+use super::captions::YouTubeCaptionTextSnippet;
+use html_entities::decode_html_entities;
+use std::fmt;
+
+// Transcript file formats we can import from other tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    Srt,
+    Vtt,
+}
+
+impl TranscriptFormat {
+    pub fn from_name(name: &str) -> Option<TranscriptFormat> {
+        match name.to_lowercase().as_str() {
+            "srt" => Some(TranscriptFormat::Srt),
+            "vtt" | "webvtt" => Some(TranscriptFormat::Vtt),
+            _ => None,
+        }
+    }
+
+    // WebVTT files must start with a `WEBVTT` line, anything else we treat as srt
+    pub fn detect(data: &str) -> TranscriptFormat {
+        if normalize_newlines(data).trim_start().starts_with("WEBVTT") {
+            TranscriptFormat::Vtt
+        } else {
+            TranscriptFormat::Srt
+        }
+    }
+}
+
+// Formats we can hand a transcript out in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Srt,
+    Vtt,
+    Txt,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name.to_lowercase().as_str() {
+            "srt" => Some(ExportFormat::Srt),
+            "vtt" | "webvtt" => Some(ExportFormat::Vtt),
+            "txt" | "text" => Some(ExportFormat::Txt),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TranscriptError {
+    MissingHeader,
+    InvalidTimestamp(String),
+    NoCues,
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptError::MissingHeader => write!(f, "WebVTT files must start with WEBVTT"),
+            TranscriptError::InvalidTimestamp(line) => {
+                write!(f, "Invalid cue timing \"{line}\"")
+            }
+            TranscriptError::NoCues => write!(f, "The transcript doesn't contain any cues"),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptError {}
+
+pub fn parse_transcript(
+    data: &str,
+    format: TranscriptFormat,
+) -> Result<Vec<YouTubeCaptionTextSnippet>, TranscriptError> {
+    match format {
+        TranscriptFormat::Srt => parse_srt(data),
+        TranscriptFormat::Vtt => parse_vtt(data),
+    }
+}
+
+// Parses SubRip cues:
+//
+//   1
+//   00:00:01,000 --> 00:00:04,000
+//   First line
+//   second line
+pub fn parse_srt(data: &str) -> Result<Vec<YouTubeCaptionTextSnippet>, TranscriptError> {
+    let data = normalize_newlines(data);
+    let mut captions_list: Vec<YouTubeCaptionTextSnippet> = vec![];
+
+    for block in cue_blocks(&data) {
+        if let Some(caption) = parse_cue(&block)? {
+            captions_list.push(caption);
+        }
+    }
+
+    if captions_list.is_empty() {
+        return Err(TranscriptError::NoCues);
+    }
+
+    Ok(captions_list)
+}
+
+// Parses WebVTT cues, skipping the header and any NOTE, STYLE and REGION blocks
+pub fn parse_vtt(data: &str) -> Result<Vec<YouTubeCaptionTextSnippet>, TranscriptError> {
+    let data = normalize_newlines(data);
+
+    if !data.trim_start().starts_with("WEBVTT") {
+        return Err(TranscriptError::MissingHeader);
+    }
+
+    let mut captions_list: Vec<YouTubeCaptionTextSnippet> = vec![];
+
+    for block in cue_blocks(&data).into_iter().skip(1) {
+        let first_line = block[0];
+        if first_line.starts_with("NOTE")
+            || first_line.starts_with("STYLE")
+            || first_line.starts_with("REGION")
+        {
+            continue;
+        }
+
+        if let Some(caption) = parse_cue(&block)? {
+            captions_list.push(caption);
+        }
+    }
+
+    if captions_list.is_empty() {
+        return Err(TranscriptError::NoCues);
+    }
+
+    Ok(captions_list)
+}
+
+fn normalize_newlines(data: &str) -> String {
+    data.trim_start_matches('\u{feff}')
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+}
+
+// Splits the file on blank lines
+fn cue_blocks(data: &str) -> Vec<Vec<&str>> {
+    let mut blocks: Vec<Vec<&str>> = vec![];
+    let mut block: Vec<&str> = vec![];
+
+    for line in data.lines() {
+        if line.trim().is_empty() {
+            if !block.is_empty() {
+                blocks.push(std::mem::take(&mut block));
+            }
+        } else {
+            block.push(line);
+        }
+    }
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+// A cue is an optional identifier line, the timing line, then the text. Both
+// formats share this shape, they only differ in the millisecond separator.
+fn parse_cue(block: &[&str]) -> Result<Option<YouTubeCaptionTextSnippet>, TranscriptError> {
+    let timing_idx = match block.iter().position(|line| line.contains("-->")) {
+        Some(idx) => idx,
+        None => return Ok(None),
+    };
+
+    let timing_line = block[timing_idx];
+    let (start, end) = timing_line
+        .split_once("-->")
+        .ok_or_else(|| TranscriptError::InvalidTimestamp(timing_line.to_string()))?;
+    // VTT cue settings (`align:start position:0%`) follow the end time
+    let end = end.split_whitespace().next().unwrap_or_default();
+
+    let start = parse_timestamp(start.trim())
+        .ok_or_else(|| TranscriptError::InvalidTimestamp(timing_line.to_string()))?;
+    let end = parse_timestamp(end)
+        .ok_or_else(|| TranscriptError::InvalidTimestamp(timing_line.to_string()))?;
+
+    let text = block[timing_idx + 1..]
+        .iter()
+        .map(|line| strip_tags(line))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let text = decode_html_entities(&text).unwrap_or(text);
+
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(YouTubeCaptionTextSnippet {
+        text,
+        start,
+        duration: (end - start).max(0.0),
+        words: None,
+    }))
+}
+
+pub fn render_transcript(captions: &[YouTubeCaptionTextSnippet], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Srt => render_srt(captions),
+        ExportFormat::Vtt => render_vtt(captions),
+        ExportFormat::Txt => render_txt(captions),
+        ExportFormat::Json => render_json(captions),
+    }
+}
+
+pub fn render_srt(captions: &[YouTubeCaptionTextSnippet]) -> String {
+    let mut srt = String::new();
+
+    for (idx, caption) in captions.iter().enumerate() {
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            idx + 1,
+            format_timestamp(caption.start, ','),
+            format_timestamp(caption.start + caption.duration, ','),
+            caption.text
+        ));
+    }
+
+    srt
+}
+
+pub fn render_vtt(captions: &[YouTubeCaptionTextSnippet]) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+
+    for caption in captions {
+        vtt.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(caption.start, '.'),
+            format_timestamp(caption.start + caption.duration, '.'),
+            // Cue text is markup, so `<` would start a tag; escaping `>` also
+            // keeps `-->` inside the text from ending the cue early
+            caption
+                .text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        ));
+    }
+
+    vtt
+}
+
+pub fn render_txt(captions: &[YouTubeCaptionTextSnippet]) -> String {
+    captions
+        .iter()
+        .map(|caption| format!("{}\n", caption.text))
+        .collect()
+}
+
+pub fn render_json(captions: &[YouTubeCaptionTextSnippet]) -> String {
+    serde_json::to_string(captions).unwrap_or_else(|_| "[]".to_string())
+}
+
+// Formats seconds as `HH:MM:SS,mmm` (srt) or `HH:MM:SS.mmm` (vtt)
+fn format_timestamp(seconds: f32, separator: char) -> String {
+    let total_ms = (seconds.max(0.0) as f64 * 1000.0).round() as u64;
+    let hours = total_ms / 3_600_000;
+    let minutes = total_ms / 60_000 % 60;
+    let secs = total_ms / 1000 % 60;
+    let ms = total_ms % 1000;
+
+    format!("{hours:02}:{minutes:02}:{secs:02}{separator}{ms:03}")
+}
+
+// Accepts `HH:MM:SS,mmm`, `HH:MM:SS.mmm` and `MM:SS.mmm`, returning seconds
+fn parse_timestamp(value: &str) -> Option<f32> {
+    let value = value.replace(',', ".");
+    let parts: Vec<&str> = value.split(':').collect();
+
+    let (hours, minutes, seconds) = match parts.as_slice() {
+        [hours, minutes, seconds] => (hours.parse::<u32>().ok()?, *minutes, *seconds),
+        [minutes, seconds] => (0, *minutes, *seconds),
+        _ => return None,
+    };
+    let minutes = minutes.parse::<u32>().ok()?;
+    let seconds = seconds.parse::<f32>().ok()?;
+
+    if minutes >= 60 || !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+
+    Some((hours * 3600 + minutes * 60) as f32 + seconds)
+}
+
+// Drops formatting like `<i>`, `<font color=...>`, `<c.colorE5E5E5>` and the
+// inline `<00:00:01.000>` timestamps from cue text
+fn strip_tags(line: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_srt_cues() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:04,500\r\n<i>Hello</i> there,\r\nfriend\r\n\r\n2\r\n01:02:03,250 --> 01:02:05,000\r\nTom &amp; Jerry\r\n\r\n";
+        let captions = parse_srt(srt).unwrap();
+
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions[0].text, "Hello there, friend");
+        assert_eq!(captions[0].start, 1.0);
+        assert_eq!(captions[0].duration, 3.5);
+        assert_eq!(captions[1].text, "Tom & Jerry");
+        assert_eq!(captions[1].start, 3723.25);
+        assert_eq!(captions[1].duration, 1.75);
+    }
+
+    #[test]
+    fn parse_vtt_cues() {
+        let vtt = "WEBVTT - exported\nKind: captions\n\nNOTE this is ignored\n\nSTYLE\n::cue { color: red }\n\nintro\n00:01.000 --> 00:03.000 align:start position:0%\n<c.colorE5E5E5>Hello</c><00:00:02.000><c> world</c>\n\n00:00:03.500 --> 00:00:05.000\nSecond cue\n";
+        let captions = parse_vtt(vtt).unwrap();
+
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions[0].text, "Hello world");
+        assert_eq!(captions[0].start, 1.0);
+        assert_eq!(captions[0].duration, 2.0);
+        assert_eq!(captions[1].text, "Second cue");
+        assert_eq!(captions[1].start, 3.5);
+    }
+
+    #[test]
+    fn parse_transcript_errors() {
+        assert_eq!(
+            parse_vtt("00:01.000 --> 00:03.000\nHi\n").unwrap_err(),
+            TranscriptError::MissingHeader
+        );
+        assert_eq!(
+            parse_srt("just some text\n").unwrap_err(),
+            TranscriptError::NoCues
+        );
+        assert_eq!(
+            parse_srt("1\n00:00:01,000 --> later\nHi\n").unwrap_err(),
+            TranscriptError::InvalidTimestamp("00:00:01,000 --> later".to_string())
+        );
+        assert_eq!(
+            parse_srt("1\n00:00:61,000 --> 00:01:02,000\nHi\n").unwrap_err(),
+            TranscriptError::InvalidTimestamp("00:00:61,000 --> 00:01:02,000".to_string())
+        );
+    }
+
+    fn caption(text: &str, start: f32, duration: f32) -> YouTubeCaptionTextSnippet {
+        YouTubeCaptionTextSnippet {
+            text: text.to_string(),
+            start,
+            duration,
+            words: None,
+        }
+    }
+
+    #[test]
+    fn render_transcripts() {
+        let captions = vec![
+            caption("Hello there", 1.0, 2.5),
+            caption("General Kenobi", 3723.25, 1.75),
+        ];
+
+        assert_eq!(
+            render_srt(&captions),
+            "1\n00:00:01,000 --> 00:00:03,500\nHello there\n\n2\n01:02:03,250 --> 01:02:05,000\nGeneral Kenobi\n\n"
+        );
+        assert_eq!(
+            render_vtt(&captions),
+            "WEBVTT\n\n00:00:01.000 --> 00:00:03.500\nHello there\n\n01:02:03.250 --> 01:02:05.000\nGeneral Kenobi\n\n"
+        );
+        assert_eq!(render_txt(&captions), "Hello there\nGeneral Kenobi\n");
+        assert_eq!(
+            render_json(&captions[..1]),
+            r#"[{"text":"Hello there","start":1.0,"duration":2.5}]"#
+        );
+    }
+
+    #[test]
+    fn render_vtt_escapes_cue_text() {
+        let captions = vec![caption("Tom & Jerry <3 --> <i>not italic</i>", 1.0, 2.0)];
+        let rendered = render_vtt(&captions);
+
+        assert_eq!(
+            rendered,
+            "WEBVTT\n\n00:00:01.000 --> 00:00:03.000\nTom &amp; Jerry &lt;3 --&gt; &lt;i&gt;not italic&lt;/i&gt;\n\n"
+        );
+        assert_eq!(
+            parse_vtt(&rendered).unwrap()[0].text,
+            "Tom & Jerry <3 --> <i>not italic</i>"
+        );
+    }
+
+    #[test]
+    fn rendered_transcripts_parse_back() {
+        let captions = vec![
+            caption("Hello there", 1.0, 2.5),
+            caption("General Kenobi", 3723.25, 1.75),
+        ];
+
+        for format in [TranscriptFormat::Srt, TranscriptFormat::Vtt] {
+            let rendered = match format {
+                TranscriptFormat::Srt => render_srt(&captions),
+                TranscriptFormat::Vtt => render_vtt(&captions),
+            };
+            let parsed = parse_transcript(&rendered, format).unwrap();
+
+            assert_eq!(parsed.len(), 2);
+            assert_eq!(parsed[1].text, "General Kenobi");
+            assert_eq!(parsed[1].start, 3723.25);
+            assert_eq!(parsed[1].duration, 1.75);
+        }
+    }
+
+    #[test]
+    fn detect_transcript_format() {
+        assert_eq!(
+            TranscriptFormat::detect("\u{feff}WEBVTT\n\n"),
+            TranscriptFormat::Vtt
+        );
+        assert_eq!(
+            TranscriptFormat::detect("1\n00:00:01,000 --> 00:00:02,000\nHi"),
+            TranscriptFormat::Srt
+        );
+    }
+}
@@ -0,0 +1,497 @@
+use std::fmt;
+use url::Url;
+
+#[derive(Debug, PartialEq)]
+pub enum YouTubeUrlError {
+    Empty,
+    InvalidUrl(String),
+    NotYouTube(String),
+    MissingVideoId(String),
+    InvalidVideoId(String),
+    MissingPlaylistId(String),
+    InvalidPlaylistId(String),
+    MissingChannel(String),
+    InvalidChannelId(String),
+}
+
+impl fmt::Display for YouTubeUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YouTubeUrlError::Empty => write!(f, "No url was given"),
+            YouTubeUrlError::InvalidUrl(url) => write!(f, "\"{url}\" is not a valid url"),
+            YouTubeUrlError::NotYouTube(url) => write!(f, "\"{url}\" is not a YouTube url"),
+            YouTubeUrlError::MissingVideoId(url) => {
+                write!(f, "Could not find a video id in \"{url}\"")
+            }
+            YouTubeUrlError::InvalidVideoId(id) => {
+                write!(f, "\"{id}\" is not a valid YouTube video id")
+            }
+            YouTubeUrlError::MissingPlaylistId(url) => {
+                write!(f, "Could not find a playlist id in \"{url}\"")
+            }
+            YouTubeUrlError::InvalidPlaylistId(id) => {
+                write!(f, "\"{id}\" is not a valid YouTube playlist id")
+            }
+            YouTubeUrlError::MissingChannel(url) => write!(
+                f,
+                "Could not find a channel id, @handle or username in \"{url}\""
+            ),
+            YouTubeUrlError::InvalidChannelId(id) => {
+                write!(f, "\"{id}\" is not a valid YouTube channel id")
+            }
+        }
+    }
+}
+
+impl std::error::Error for YouTubeUrlError {}
+
+// Video ids are always 11 characters of [A-Za-z0-9_-]
+pub fn is_video_id(s: &str) -> bool {
+    s.len() == 11
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Pulls the video id out of anything a user might paste in:
+//
+//   dQw4w9WgXcQ
+//   https://youtu.be/dQw4w9WgXcQ?t=42
+//   https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL...
+//   https://m.youtube.com/watch?v=dQw4w9WgXcQ
+//   https://music.youtube.com/watch?v=dQw4w9WgXcQ
+//   https://www.youtube.com/shorts/dQw4w9WgXcQ
+//   https://www.youtube.com/embed/dQw4w9WgXcQ
+//   https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ
+//   https://www.youtube.com/live/dQw4w9WgXcQ
+//   youtube.com/watch?v=dQw4w9WgXcQ (no scheme)
+pub fn parse_video_id(input: &str) -> Result<String, YouTubeUrlError> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return Err(YouTubeUrlError::Empty);
+    }
+    if is_video_id(input) {
+        return Ok(input.to_string());
+    }
+
+    let url = parse_youtube_url(input)?;
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    let video_id = if host == "youtu.be" {
+        segments.first().map(|id| id.to_string())
+    } else {
+        match segments.as_slice() {
+            ["watch", ..] => url
+                .query_pairs()
+                .find(|(key, _)| key == "v")
+                .map(|(_, value)| value.into_owned()),
+            ["shorts" | "embed" | "live" | "v" | "e", id, ..] => Some(id.to_string()),
+            _ => None,
+        }
+    };
+
+    let video_id = video_id.ok_or_else(|| YouTubeUrlError::MissingVideoId(input.to_string()))?;
+
+    if !is_video_id(&video_id) {
+        return Err(YouTubeUrlError::InvalidVideoId(video_id));
+    }
+
+    Ok(video_id)
+}
+
+// Playlist ids are longer than video ids and use the same characters. Their
+// length depends on the kind of playlist (PL..., UU..., OLAK5uy_... etc.)
+pub fn is_playlist_id(s: &str) -> bool {
+    (13..=64).contains(&s.len())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Pulls the playlist id out of a bare id, `list=<id>`, or any YouTube url with
+// a `list` parameter (playlist pages as well as watch urls)
+pub fn parse_playlist_id(input: &str) -> Result<String, YouTubeUrlError> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return Err(YouTubeUrlError::Empty);
+    }
+    let bare_id = input.strip_prefix("list=").unwrap_or(input);
+    if is_playlist_id(bare_id) {
+        return Ok(bare_id.to_string());
+    }
+
+    let url = parse_youtube_url(input)?;
+    let playlist_id = url
+        .query_pairs()
+        .find(|(key, _)| key == "list")
+        .map(|(_, value)| value.into_owned())
+        .ok_or_else(|| YouTubeUrlError::MissingPlaylistId(input.to_string()))?;
+
+    if !is_playlist_id(&playlist_id) {
+        return Err(YouTubeUrlError::InvalidPlaylistId(playlist_id));
+    }
+
+    Ok(playlist_id)
+}
+
+// The ways a channel can be named, each of which the Data API can look up
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelRef {
+    Id(String),
+    // Without the leading @
+    Handle(String),
+    // Legacy /user/<name> urls
+    Username(String),
+}
+
+// Channel ids are "UC" followed by 22 characters of [A-Za-z0-9_-]
+pub fn is_channel_id(s: &str) -> bool {
+    s.len() == 24
+        && s.starts_with("UC")
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn is_handle(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+// Works out which channel the input names:
+//
+//   UCuAXFkgsw1L7xaCfnd5JJOw
+//   @RickAstleyYT
+//   https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw/videos
+//   https://www.youtube.com/@RickAstleyYT
+//   https://www.youtube.com/user/RickAstleyVEVO
+//
+// Custom /c/<name> urls aren't supported since the Data API can't look them up.
+pub fn parse_channel(input: &str) -> Result<ChannelRef, YouTubeUrlError> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return Err(YouTubeUrlError::Empty);
+    }
+    if is_channel_id(input) {
+        return Ok(ChannelRef::Id(input.to_string()));
+    }
+    if let Some(handle) = input.strip_prefix('@') {
+        if is_handle(handle) {
+            return Ok(ChannelRef::Handle(handle.to_string()));
+        }
+    }
+
+    let url = parse_youtube_url(input)?;
+    let segments: Vec<String> = url
+        .path_segments()
+        .map(|segments| {
+            segments
+                .filter(|s| !s.is_empty())
+                .map(|s| {
+                    percent_encoding::percent_decode_str(s)
+                        .decode_utf8_lossy()
+                        .into_owned()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+
+    match segments.as_slice() {
+        ["channel", id, ..] if is_channel_id(id) => Ok(ChannelRef::Id(id.to_string())),
+        ["channel", id, ..] => Err(YouTubeUrlError::InvalidChannelId(id.to_string())),
+        ["user", username, ..] => Ok(ChannelRef::Username(username.to_string())),
+        [first, ..] if first.starts_with('@') && is_handle(&first[1..]) => {
+            Ok(ChannelRef::Handle(first[1..].to_string()))
+        }
+        _ => Err(YouTubeUrlError::MissingChannel(input.to_string())),
+    }
+}
+
+// Parses the input as a url on one of YouTube's domains, adding a scheme if the
+// user left it off
+pub fn parse_youtube_url(input: &str) -> Result<Url, YouTubeUrlError> {
+    let with_scheme = if input.contains("://") {
+        input.to_string()
+    } else {
+        format!("https://{input}")
+    };
+
+    let url =
+        Url::parse(&with_scheme).map_err(|_| YouTubeUrlError::InvalidUrl(input.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(YouTubeUrlError::InvalidUrl(input.to_string()));
+    }
+
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    let is_youtube = host == "youtu.be"
+        || ["youtube.com", "youtube-nocookie.com"]
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{domain}")));
+
+    if !is_youtube {
+        return Err(YouTubeUrlError::NotYouTube(input.to_string()));
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "dQw4w9WgXcQ";
+
+    #[test]
+    fn parses_every_url_shape() {
+        let cases = [
+            "dQw4w9WgXcQ",
+            "  dQw4w9WgXcQ  ",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?t=42",
+            "https://youtu.be/dQw4w9WgXcQ?si=abc123&t=42",
+            "http://youtu.be/dQw4w9WgXcQ/",
+            "youtu.be/dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42s",
+            "https://www.youtube.com/watch?feature=share&v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI&index=2",
+            "https://www.youtube.com/watch/?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?v=dQw4w9WgXcQ",
+            "youtube.com/watch?v=dQw4w9WgXcQ",
+            "www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "HTTPS://WWW.YOUTUBE.COM/watch?v=dQw4w9WgXcQ",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&feature=share",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://youtube.com/shorts/dQw4w9WgXcQ?feature=share",
+            "https://www.youtube.com/embed/dQw4w9WgXcQ",
+            "https://www.youtube.com/embed/dQw4w9WgXcQ?start=30&autoplay=1",
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ",
+            "https://youtube-nocookie.com/embed/dQw4w9WgXcQ?rel=0",
+            "https://www.youtube.com/live/dQw4w9WgXcQ",
+            "https://www.youtube.com/live/dQw4w9WgXcQ?si=abc123",
+            "https://www.youtube.com/v/dQw4w9WgXcQ",
+            "https://www.youtube.com/e/dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ#comments",
+        ];
+
+        for case in cases {
+            assert_eq!(parse_video_id(case), Ok(ID.to_string()), "{case}");
+        }
+    }
+
+    #[test]
+    fn keeps_dashes_and_underscores() {
+        assert_eq!(
+            parse_video_id("https://youtu.be/a-b_c-d_e-f"),
+            Ok("a-b_c-d_e-f".to_string())
+        );
+        assert_eq!(parse_video_id("_-_-_-_-_-_"), Ok("_-_-_-_-_-_".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let cases = [
+            ("", YouTubeUrlError::Empty),
+            ("   ", YouTubeUrlError::Empty),
+            (
+                "not a url at all",
+                YouTubeUrlError::InvalidUrl("not a url at all".to_string()),
+            ),
+            (
+                "ftp://youtube.com/watch?v=dQw4w9WgXcQ",
+                YouTubeUrlError::InvalidUrl("ftp://youtube.com/watch?v=dQw4w9WgXcQ".to_string()),
+            ),
+            (
+                "https://vimeo.com/123456",
+                YouTubeUrlError::NotYouTube("https://vimeo.com/123456".to_string()),
+            ),
+            (
+                "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+                YouTubeUrlError::NotYouTube(
+                    "https://notyoutube.com/watch?v=dQw4w9WgXcQ".to_string(),
+                ),
+            ),
+            (
+                "https://www.youtube.com/watch",
+                YouTubeUrlError::MissingVideoId("https://www.youtube.com/watch".to_string()),
+            ),
+            (
+                "https://www.youtube.com/watch?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+                YouTubeUrlError::MissingVideoId(
+                    "https://www.youtube.com/watch?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI"
+                        .to_string(),
+                ),
+            ),
+            (
+                "https://www.youtube.com/@somechannel",
+                YouTubeUrlError::MissingVideoId("https://www.youtube.com/@somechannel".to_string()),
+            ),
+            (
+                "https://youtu.be/",
+                YouTubeUrlError::MissingVideoId("https://youtu.be/".to_string()),
+            ),
+            (
+                "https://www.youtube.com/shorts/",
+                YouTubeUrlError::MissingVideoId("https://www.youtube.com/shorts/".to_string()),
+            ),
+            (
+                "https://www.youtube.com/watch?v=short",
+                YouTubeUrlError::InvalidVideoId("short".to_string()),
+            ),
+            (
+                "https://youtu.be/dQw4w9WgXcQextra",
+                YouTubeUrlError::InvalidVideoId("dQw4w9WgXcQextra".to_string()),
+            ),
+            (
+                "https://www.youtube.com/embed/dQw4w9Wg!cQ",
+                YouTubeUrlError::InvalidVideoId("dQw4w9Wg!cQ".to_string()),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_video_id(input), Err(expected), "{input}");
+        }
+    }
+
+    const PLAYLIST_ID: &str = "PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI";
+
+    #[test]
+    fn parses_playlist_ids() {
+        let cases = [
+            "PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+            " PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI ",
+            "list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+            "https://www.youtube.com/playlist?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+            "youtube.com/playlist?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+            "https://m.youtube.com/playlist?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI&si=abc123",
+            "https://music.youtube.com/playlist?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI&index=2",
+            "https://youtu.be/dQw4w9WgXcQ?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+            "https://www.youtube.com/embed/videoseries?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+        ];
+
+        for case in cases {
+            assert_eq!(
+                parse_playlist_id(case),
+                Ok(PLAYLIST_ID.to_string()),
+                "{case}"
+            );
+        }
+
+        assert_eq!(
+            parse_playlist_id("UUuAXFkgsw1L7xaCfnd5JJOw"),
+            Ok("UUuAXFkgsw1L7xaCfnd5JJOw".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_playlist_input() {
+        let cases = [
+            ("", YouTubeUrlError::Empty),
+            (
+                "https://vimeo.com/showcase/123?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI",
+                YouTubeUrlError::NotYouTube(
+                    "https://vimeo.com/showcase/123?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI"
+                        .to_string(),
+                ),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+                YouTubeUrlError::MissingPlaylistId(
+                    "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+                ),
+            ),
+            (
+                "dQw4w9WgXcQ",
+                YouTubeUrlError::NotYouTube("dQw4w9WgXcQ".to_string()),
+            ),
+            (
+                "https://www.youtube.com/playlist?list=PL!bad",
+                YouTubeUrlError::InvalidPlaylistId("PL!bad".to_string()),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_playlist_id(input), Err(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn parses_channels() {
+        let id = || ChannelRef::Id("UCuAXFkgsw1L7xaCfnd5JJOw".to_string());
+        let handle = || ChannelRef::Handle("RickAstleyYT".to_string());
+        let cases = [
+            ("UCuAXFkgsw1L7xaCfnd5JJOw", id()),
+            (" UCuAXFkgsw1L7xaCfnd5JJOw ", id()),
+            (
+                "https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw",
+                id(),
+            ),
+            (
+                "https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw/videos",
+                id(),
+            ),
+            (
+                "youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw?si=abc123",
+                id(),
+            ),
+            (
+                "https://m.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw",
+                id(),
+            ),
+            ("@RickAstleyYT", handle()),
+            ("https://www.youtube.com/@RickAstleyYT", handle()),
+            ("https://www.youtube.com/@RickAstleyYT/videos", handle()),
+            ("youtube.com/@RickAstleyYT", handle()),
+            (
+                "https://www.youtube.com/@%E3%83%AA%E3%83%83%E3%82%AF",
+                ChannelRef::Handle("リック".to_string()),
+            ),
+            (
+                "https://www.youtube.com/user/RickAstleyVEVO",
+                ChannelRef::Username("RickAstleyVEVO".to_string()),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_channel(input), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_channel_input() {
+        let cases = [
+            ("", YouTubeUrlError::Empty),
+            (
+                "https://vimeo.com/rick",
+                YouTubeUrlError::NotYouTube("https://vimeo.com/rick".to_string()),
+            ),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+                YouTubeUrlError::MissingChannel(
+                    "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+                ),
+            ),
+            (
+                "https://www.youtube.com/c/RickAstley",
+                YouTubeUrlError::MissingChannel("https://www.youtube.com/c/RickAstley".to_string()),
+            ),
+            (
+                "https://www.youtube.com/channel/UCshort",
+                YouTubeUrlError::InvalidChannelId("UCshort".to_string()),
+            ),
+            ("@", YouTubeUrlError::InvalidUrl("@".to_string())),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_channel(input), Err(expected), "{input}");
+        }
+    }
+}
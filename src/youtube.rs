@@ -0,0 +1,669 @@
+use crate::quota::{ApiCall, Quota, QuotaError};
+use crate::utils::http::HttpClient;
+use crate::utils::youtube_url::ChannelRef;
+use chrono::{DateTime, Utc};
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use url::Url;
+
+pub const DEFAULT_API_URL: &str = "https://www.googleapis.com/youtube/v3";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeVideoResponse {
+    #[serde(default)]
+    items: Vec<YouTubeVideoItem>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeVideoItem {
+    id: String,
+    snippet: YouTubeVideoSnippet,
+    #[serde(default)]
+    statistics: YouTubeVideoStatistics,
+    #[serde(rename = "contentDetails")]
+    content_details: YouTubeVideoContentDetails,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeVideoSnippet {
+    #[serde(rename = "publishedAt")]
+    published_at: String,
+    #[serde(rename = "channelId")]
+    channel_id: String,
+    title: String,
+    thumbnails: YouTubeThumbnailTypes,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeVideoContentDetails {
+    duration: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeVideoStatistics {
+    // Missing when the uploader has hidden the view count
+    #[serde(rename = "viewCount", default)]
+    view_count: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeThumbnailTypes {
+    default: YouTubeThumbnail,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeThumbnail {
+    url: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeChannelResponse {
+    // Missing entirely when nothing matched a handle or username
+    #[serde(default)]
+    items: Vec<YouTubeChannelItem>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeChannelItem {
+    id: String,
+    snippet: YouTubeChannelSnippet,
+    #[serde(rename = "contentDetails", default)]
+    content_details: YouTubeChannelContentDetails,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeChannelSnippet {
+    title: String,
+    thumbnails: YouTubeThumbnailTypes,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeChannelContentDetails {
+    #[serde(rename = "relatedPlaylists")]
+    related_playlists: YouTubeChannelRelatedPlaylists,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubeChannelRelatedPlaylists {
+    uploads: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubePlaylistItemsResponse {
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+    #[serde(default)]
+    items: Vec<YouTubePlaylistItem>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubePlaylistItem {
+    #[serde(rename = "contentDetails")]
+    content_details: YouTubePlaylistItemContentDetails,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct YouTubePlaylistItemContentDetails {
+    #[serde(rename = "videoId")]
+    video_id: String,
+}
+
+// The body Google's APIs send back with an error status
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct GoogleErrorResponse {
+    error: GoogleError,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct GoogleError {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    errors: Vec<GoogleErrorDetail>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct GoogleErrorDetail {
+    #[serde(default)]
+    reason: String,
+}
+
+// The parts of a video we keep. Fields are as YouTube sends them; parsing the
+// duration and so on is up to the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoDetails {
+    pub youtube_id: String,
+    pub channel_youtube_id: String,
+    pub title: String,
+    pub published_at: String,
+    pub duration: String,
+    pub view_count: Option<String>,
+    pub thumbnail: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelDetails {
+    pub youtube_id: String,
+    pub title: String,
+    pub thumbnail: String,
+    pub uploads_playlist_id: String,
+}
+
+#[derive(Debug)]
+pub enum YouTubeApiError {
+    // Google refused the request, having run out of quota
+    QuotaExceeded(String),
+    // We stopped short of making the request, to stay within our own budget
+    BudgetExhausted(DateTime<Utc>),
+    InvalidKey(String),
+    VideoNotFound(String),
+    ChannelNotFound(String),
+    PlaylistNotFound(String),
+    // Any other error status, with Google's message
+    Api(u16, String),
+    Request(reqwest::Error),
+    // Couldn't record the call against our quota
+    QuotaTracking(sqlx::Error),
+}
+
+impl fmt::Display for YouTubeApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YouTubeApiError::QuotaExceeded(message) => {
+                write!(f, "The YouTube Data API quota has run out: {message}")
+            }
+            YouTubeApiError::BudgetExhausted(resets_at) => write!(
+                f,
+                "Today's YouTube Data API budget is used up; it resets at {resets_at}"
+            ),
+            YouTubeApiError::InvalidKey(message) => {
+                write!(f, "The YouTube Data API key was rejected: {message}")
+            }
+            YouTubeApiError::VideoNotFound(id) => write!(f, "YouTube has no video with id {id}"),
+            YouTubeApiError::ChannelNotFound(id) => write!(f, "YouTube has no channel {id}"),
+            YouTubeApiError::PlaylistNotFound(id) => {
+                write!(f, "YouTube has no playlist with id {id}")
+            }
+            YouTubeApiError::Api(status, message) => {
+                write!(f, "The YouTube Data API returned {status}: {message}")
+            }
+            YouTubeApiError::Request(e) => write!(f, "Request to the YouTube API failed: {e}"),
+            YouTubeApiError::QuotaTracking(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for YouTubeApiError {}
+
+impl From<reqwest::Error> for YouTubeApiError {
+    fn from(e: reqwest::Error) -> Self {
+        // The url has our API key in it, and these errors end up stored on jobs
+        YouTubeApiError::Request(e.without_url())
+    }
+}
+
+impl From<QuotaError> for YouTubeApiError {
+    fn from(e: QuotaError) -> Self {
+        match e {
+            QuotaError::Exhausted { resets_at } => YouTubeApiError::BudgetExhausted(resets_at),
+            QuotaError::Database(e) => YouTubeApiError::QuotaTracking(e),
+        }
+    }
+}
+
+impl YouTubeApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            YouTubeApiError::QuotaExceeded(_) => "quota_exceeded",
+            YouTubeApiError::BudgetExhausted(_) => "quota_budget_exhausted",
+            YouTubeApiError::InvalidKey(_) => "invalid_api_key",
+            YouTubeApiError::VideoNotFound(_) => "video_not_found",
+            YouTubeApiError::ChannelNotFound(_) => "channel_not_found",
+            YouTubeApiError::PlaylistNotFound(_) => "playlist_not_found",
+            YouTubeApiError::Api(_, _) => "youtube_api_error",
+            YouTubeApiError::Request(_) => "youtube_request_failed",
+            YouTubeApiError::QuotaTracking(_) => "database_error",
+        }
+    }
+}
+
+// The Data API calls we make. `DataApiClient` is the real thing; tests can
+// point it at a local server, or swap in their own implementation.
+#[rocket::async_trait]
+pub trait YouTubeApi: Send + Sync {
+    // videos.list, for up to 50 ids. Ids YouTube doesn't know are left out.
+    async fn videos(&self, video_ids: &[String]) -> Result<Vec<VideoDetails>, YouTubeApiError>;
+
+    // channels.list
+    async fn channel(&self, channel: &ChannelRef) -> Result<ChannelDetails, YouTubeApiError>;
+
+    // playlistItems.list, following `nextPageToken` until we've seen every
+    // page. Ids are in playlist order with duplicates removed.
+    async fn playlist_video_ids(&self, playlist_id: &str) -> Result<Vec<String>, YouTubeApiError>;
+
+    async fn video(&self, video_id: &str) -> Result<VideoDetails, YouTubeApiError> {
+        self.videos(&[video_id.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| YouTubeApiError::VideoNotFound(video_id.to_string()))
+    }
+}
+
+pub struct DataApiClient {
+    http: HttpClient,
+    api_key: String,
+    base_url: String,
+    quota: Option<Quota>,
+}
+
+impl DataApiClient {
+    pub fn new(http: HttpClient, api_key: String, base_url: String) -> DataApiClient {
+        DataApiClient {
+            http,
+            api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            quota: None,
+        }
+    }
+
+    // Counts every call against `quota`, refusing any that would go over its
+    // daily budget
+    pub fn with_quota(mut self, quota: Quota) -> DataApiClient {
+        self.quota = Some(quota);
+        self
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        call: ApiCall,
+        params: &[(&str, &str)],
+    ) -> Result<T, YouTubeApiError> {
+        let mut url = Url::parse(&format!("{}/{}", self.base_url, call.resource()))
+            .map_err(|e| YouTubeApiError::Api(0, format!("Invalid Data API url: {e}")))?;
+        url.query_pairs_mut()
+            .append_pair("key", &self.api_key)
+            .extend_pairs(params);
+
+        // Retries are charged for too
+        let response = self
+            .http
+            .get_with(url, || async {
+                if let Some(quota) = &self.quota {
+                    quota.spend(call).await?;
+                }
+                Ok::<(), YouTubeApiError>(())
+            })
+            .await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json::<T>().await?);
+        }
+
+        let error = response
+            .json::<GoogleErrorResponse>()
+            .await
+            .map(|body| body.error)
+            .unwrap_or_default();
+        let reasons: Vec<&str> = error.errors.iter().map(|e| e.reason.as_str()).collect();
+        let message = error.message;
+
+        Err(
+            if reasons
+                .iter()
+                .any(|r| matches!(*r, "quotaExceeded" | "dailyLimitExceeded"))
+            {
+                YouTubeApiError::QuotaExceeded(message)
+            } else if reasons
+                .iter()
+                .any(|r| matches!(*r, "keyInvalid" | "keyExpired"))
+                || message.contains("API key not valid")
+            {
+                YouTubeApiError::InvalidKey(message)
+            } else {
+                YouTubeApiError::Api(status.as_u16(), message)
+            },
+        )
+    }
+}
+
+#[rocket::async_trait]
+impl YouTubeApi for DataApiClient {
+    async fn videos(&self, video_ids: &[String]) -> Result<Vec<VideoDetails>, YouTubeApiError> {
+        let response: YouTubeVideoResponse = self
+            .get(
+                ApiCall::Videos,
+                &[
+                    ("part", "id,snippet,statistics,contentDetails"),
+                    ("id", &video_ids.join(",")),
+                ],
+            )
+            .await?;
+
+        Ok(response
+            .items
+            .into_iter()
+            .map(|item| VideoDetails {
+                youtube_id: item.id,
+                channel_youtube_id: item.snippet.channel_id,
+                title: item.snippet.title,
+                published_at: item.snippet.published_at,
+                duration: item.content_details.duration,
+                view_count: item.statistics.view_count,
+                thumbnail: item.snippet.thumbnails.default.url,
+            })
+            .collect())
+    }
+
+    async fn channel(&self, channel: &ChannelRef) -> Result<ChannelDetails, YouTubeApiError> {
+        let (filter, name) = match channel {
+            ChannelRef::Id(id) => ("id", id.clone()),
+            ChannelRef::Handle(handle) => ("forHandle", format!("@{handle}")),
+            ChannelRef::Username(username) => ("forUsername", username.clone()),
+        };
+
+        let response: YouTubeChannelResponse = self
+            .get(
+                ApiCall::Channels,
+                &[("part", "id,snippet,contentDetails"), (filter, &name)],
+            )
+            .await?;
+
+        let item = response
+            .items
+            .into_iter()
+            .next()
+            .ok_or(YouTubeApiError::ChannelNotFound(name))?;
+
+        Ok(ChannelDetails {
+            youtube_id: item.id,
+            title: item.snippet.title,
+            thumbnail: item.snippet.thumbnails.default.url,
+            uploads_playlist_id: item.content_details.related_playlists.uploads,
+        })
+    }
+
+    async fn playlist_video_ids(&self, playlist_id: &str) -> Result<Vec<String>, YouTubeApiError> {
+        let mut video_ids: Vec<String> = vec![];
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut params = vec![
+                ("part", "contentDetails"),
+                ("maxResults", "50"),
+                ("playlistId", playlist_id),
+            ];
+            if let Some(page_token) = &page_token {
+                params.push(("pageToken", page_token));
+            }
+
+            let page: YouTubePlaylistItemsResponse =
+                match self.get(ApiCall::PlaylistItems, &params).await {
+                    Err(YouTubeApiError::Api(404, _)) => {
+                        return Err(YouTubeApiError::PlaylistNotFound(playlist_id.to_string()))
+                    }
+                    result => result?,
+                };
+
+            for item in page.items {
+                let video_id = item.content_details.video_id;
+                if seen.insert(video_id.clone()) {
+                    video_ids.push(video_id);
+                }
+            }
+
+            match page.next_page_token {
+                Some(next_page_token) => page_token = Some(next_page_token),
+                None => return Ok(video_ids),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::http::RetryPolicy;
+    use crate::utils::test_server::{response, serve};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const VIDEO: &str = r#"{
+        "kind": "youtube#videoListResponse",
+        "items": [{
+            "kind": "youtube#video",
+            "id": "dQw4w9WgXcQ",
+            "snippet": {
+                "publishedAt": "2009-10-25T06:57:33Z",
+                "channelId": "UCuAXFkgsw1L7xaCfnd5JJOw",
+                "title": "Rick Astley - Never Gonna Give You Up (Official Music Video)",
+                "thumbnails": {"default": {"url": "https://i.ytimg.com/vi/dQw4w9WgXcQ/default.jpg", "width": 120, "height": 90}},
+                "channelTitle": "Rick Astley"
+            },
+            "contentDetails": {"duration": "PT3M33S", "dimension": "2d"},
+            "statistics": {"viewCount": "1402350861", "likeCount": "16000000"}
+        }]
+    }"#;
+
+    const NO_ITEMS: &str = r#"{"kind": "youtube#videoListResponse", "items": []}"#;
+
+    const CHANNEL: &str = r#"{
+        "items": [{
+            "id": "UCuAXFkgsw1L7xaCfnd5JJOw",
+            "snippet": {
+                "title": "Rick Astley",
+                "thumbnails": {"default": {"url": "https://yt3.ggpht.com/rick"}}
+            },
+            "contentDetails": {"relatedPlaylists": {"likes": "", "uploads": "UUuAXFkgsw1L7xaCfnd5JJOw"}}
+        }]
+    }"#;
+
+    const QUOTA_EXCEEDED: &str = r#"{"error": {"code": 403, "message": "The request cannot be completed because you have exceeded your <a href=\"/youtube/v3/getting-started#quota\">quota</a>.", "errors": [{"message": "...", "domain": "youtube.quota", "reason": "quotaExceeded"}]}}"#;
+
+    const KEY_INVALID: &str = r#"{"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "errors": [{"message": "API key not valid. Please pass a valid API key.", "domain": "global", "reason": "badRequest"}], "status": "INVALID_ARGUMENT"}}"#;
+
+    const PLAYLIST_NOT_FOUND: &str = r#"{"error": {"code": 404, "message": "The playlist identified with the request's <code>playlistId</code> parameter cannot be found.", "errors": [{"domain": "youtube.playlistItem", "reason": "playlistNotFound"}]}}"#;
+
+    fn playlist_page(video_ids: &[&str], next_page_token: Option<&str>) -> String {
+        let items: Vec<String> = video_ids
+            .iter()
+            .map(|id| format!(r#"{{"contentDetails": {{"videoId": "{id}"}}}}"#))
+            .collect();
+        let next_page_token = next_page_token
+            .map(|token| format!(r#""nextPageToken": "{token}","#))
+            .unwrap_or_default();
+
+        format!(r#"{{{next_page_token} "items": [{}]}}"#, items.join(","))
+    }
+
+    async fn client<F>(respond: F) -> (DataApiClient, Arc<Mutex<Vec<String>>>)
+    where
+        F: Fn(&str) -> String + Send + 'static,
+    {
+        let (base_url, requests) = serve(respond).await;
+        let client = DataApiClient::new(
+            HttpClient::new().unwrap(),
+            "test-key".to_string(),
+            format!("{base_url}/youtube/v3/"),
+        );
+
+        (client, requests)
+    }
+
+    fn json(status: &str, body: &str) -> String {
+        response(status, &[("Content-Type", "application/json")], body)
+    }
+
+    #[tokio::test]
+    async fn fetches_video_details() {
+        let (client, requests) = client(|_| json("200 OK", VIDEO)).await;
+
+        let video = client.video("dQw4w9WgXcQ").await.unwrap();
+
+        assert_eq!(
+            video,
+            VideoDetails {
+                youtube_id: "dQw4w9WgXcQ".to_string(),
+                channel_youtube_id: "UCuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+                title: "Rick Astley - Never Gonna Give You Up (Official Music Video)".to_string(),
+                published_at: "2009-10-25T06:57:33Z".to_string(),
+                duration: "PT3M33S".to_string(),
+                view_count: Some("1402350861".to_string()),
+                thumbnail: "https://i.ytimg.com/vi/dQw4w9WgXcQ/default.jpg".to_string(),
+            }
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["/youtube/v3/videos?key=test-key&part=id%2Csnippet%2Cstatistics%2CcontentDetails&id=dQw4w9WgXcQ"]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_videos_are_not_found() {
+        let (client, _) = client(|_| json("200 OK", NO_ITEMS)).await;
+
+        let err = client.video("dQw4w9WgXcQ").await.unwrap_err();
+
+        assert!(matches!(err, YouTubeApiError::VideoNotFound(id) if id == "dQw4w9WgXcQ"));
+    }
+
+    #[tokio::test]
+    async fn fetches_channels_by_handle() {
+        let (client, requests) = client(|_| json("200 OK", CHANNEL)).await;
+
+        let channel = client
+            .channel(&ChannelRef::Handle("RickAstleyYT".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            channel,
+            ChannelDetails {
+                youtube_id: "UCuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+                title: "Rick Astley".to_string(),
+                thumbnail: "https://yt3.ggpht.com/rick".to_string(),
+                uploads_playlist_id: "UUuAXFkgsw1L7xaCfnd5JJOw".to_string(),
+            }
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["/youtube/v3/channels?key=test-key&part=id%2Csnippet%2CcontentDetails&forHandle=%40RickAstleyYT"]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_channels_are_not_found() {
+        let (client, _) =
+            client(|_| json("200 OK", r#"{"kind": "youtube#channelListResponse"}"#)).await;
+
+        let err = client
+            .channel(&ChannelRef::Username("nobody".to_string()))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, YouTubeApiError::ChannelNotFound(name) if name == "nobody"));
+    }
+
+    #[tokio::test]
+    async fn pages_through_playlists() {
+        let (client, requests) = client(|target| {
+            if target.contains("pageToken=page2") {
+                json("200 OK", &playlist_page(&["c", "a"], None))
+            } else {
+                json("200 OK", &playlist_page(&["a", "b"], Some("page2")))
+            }
+        })
+        .await;
+
+        let video_ids = client
+            .playlist_video_ids("PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI")
+            .await
+            .unwrap();
+
+        assert_eq!(video_ids, vec!["a", "b", "c"]);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn typed_api_errors() {
+        let (client, _) = client(|target| {
+            if target.starts_with("/youtube/v3/videos") {
+                json("403 Forbidden", QUOTA_EXCEEDED)
+            } else if target.starts_with("/youtube/v3/channels") {
+                json("400 Bad Request", KEY_INVALID)
+            } else {
+                json("404 Not Found", PLAYLIST_NOT_FOUND)
+            }
+        })
+        .await;
+
+        assert!(matches!(
+            client.video("dQw4w9WgXcQ").await,
+            Err(YouTubeApiError::QuotaExceeded(_))
+        ));
+        assert!(matches!(
+            client
+                .channel(&ChannelRef::Id("UCuAXFkgsw1L7xaCfnd5JJOw".to_string()))
+                .await,
+            Err(YouTubeApiError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            client.playlist_video_ids("PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI").await,
+            Err(YouTubeApiError::PlaylistNotFound(id)) if id == "PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI"
+        ));
+    }
+
+    #[tokio::test]
+    async fn other_errors_keep_their_status() {
+        let (client, _) = client(|_| {
+            json(
+                "400 Bad Request",
+                r#"{"error": {"code": 400, "message": "Invalid filter", "errors": [{"reason": "invalidFilters"}]}}"#,
+            )
+        })
+        .await;
+
+        let err = client.video("dQw4w9WgXcQ").await.unwrap_err();
+
+        assert!(matches!(err, YouTubeApiError::Api(400, message) if message == "Invalid filter"));
+    }
+
+    #[sqlx::test]
+    async fn counts_calls_against_the_quota(pool: sqlx::PgPool) {
+        let (client, requests) = client(|_| json("200 OK", VIDEO)).await;
+        let quota = Quota::new(pool, Some(2));
+        let client = client.with_quota(quota.clone());
+
+        client.video("dQw4w9WgXcQ").await.unwrap();
+        client.video("dQw4w9WgXcQ").await.unwrap();
+        let err = client.video("dQw4w9WgXcQ").await.unwrap_err();
+
+        // The third call is refused before it's sent
+        assert!(matches!(err, YouTubeApiError::BudgetExhausted(_)));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(quota.usage().await.unwrap().calls[0].units, 2);
+    }
+
+    #[sqlx::test]
+    async fn counts_every_retry_against_the_quota(pool: sqlx::PgPool) {
+        let (base_url, requests) = serve(|_| json("503 Service Unavailable", "{}")).await;
+        let http = HttpClient::with_retry_policy(RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        })
+        .unwrap();
+        let quota = Quota::new(pool, Some(3));
+        let client =
+            DataApiClient::new(http, "test-key".to_string(), base_url).with_quota(quota.clone());
+
+        let err = client.video("dQw4w9WgXcQ").await.unwrap_err();
+
+        // Three attempts fit the budget; the fourth is refused
+        assert!(matches!(err, YouTubeApiError::BudgetExhausted(_)));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        assert_eq!(quota.usage().await.unwrap().calls[0].calls, 3);
+    }
+}